@@ -0,0 +1,4 @@
+// Migrations are embedded by `sqlx::migrate!`, so adding one must rebuild the binary.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,54 @@
+-- Baseline catalog. Written to be idempotent so databases created before
+-- migrations existed are adopted as they are.
+CREATE TABLE IF NOT EXISTS products (
+    category VARCHAR(50),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    description VARCHAR(255),
+    id SERIAL PRIMARY KEY,
+    image_url VARCHAR(255),
+    is_active BOOLEAN DEFAULT TRUE,
+    name VARCHAR(50) NOT NULL,
+    price_cents BIGINT NOT NULL,
+    stock_quantity INT NOT NULL CHECK (stock_quantity >= 0),
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Converts the legacy `price DOUBLE PRECISION` column to integer cents.
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'products' AND column_name = 'price'
+    ) THEN
+        ALTER TABLE products ADD COLUMN IF NOT EXISTS price_cents BIGINT;
+        UPDATE products SET price_cents = round(price::NUMERIC * 100)::BIGINT;
+        ALTER TABLE products ALTER COLUMN price_cents SET NOT NULL;
+        ALTER TABLE products DROP COLUMN price;
+    END IF;
+END $$;
+
+-- Tables created before stock was tracked lack the non-negative check.
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_constraint WHERE conname = 'products_stock_quantity_check'
+    ) THEN
+        ALTER TABLE products
+        ADD CONSTRAINT products_stock_quantity_check CHECK (stock_quantity >= 0);
+    END IF;
+END $$;
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS search_vector tsvector
+GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', coalesce(name, '')), 'A') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'B') ||
+    setweight(to_tsvector('english', coalesce(category, '')), 'C')
+) STORED;
+
+ALTER TABLE products ADD COLUMN IF NOT EXISTS category_slug VARCHAR(60)
+GENERATED ALWAYS AS (
+    trim(BOTH '-' FROM regexp_replace(lower(coalesce(category, '')), '[^a-z0-9]+', '-', 'g'))
+) STORED;
+
+CREATE INDEX IF NOT EXISTS products_category_slug_idx ON products (category_slug);
+CREATE INDEX IF NOT EXISTS products_search_vector_idx ON products USING GIN (search_vector);
@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS orders (
+    cart_id UUID REFERENCES carts (id) ON DELETE SET NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    currency VARCHAR(3) NOT NULL,
+    customer_email VARCHAR(255),
+    customer_name VARCHAR(255),
+    customer_phone VARCHAR(50),
+    id SERIAL PRIMARY KEY,
+    paid_at TIMESTAMP,
+    payment_intent_id VARCHAR(255) NOT NULL UNIQUE,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'paid', 'failed', 'refunded')),
+    total_cents BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Support looks orders up by the customer's email.
+CREATE INDEX IF NOT EXISTS orders_customer_email_idx ON orders (LOWER(customer_email));
+
+CREATE TABLE IF NOT EXISTS order_items (
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+    product_id INT REFERENCES products (id) ON DELETE SET NULL,
+    product_name VARCHAR(50) NOT NULL,
+    quantity INT NOT NULL CHECK (quantity > 0),
+    total_price_cents BIGINT NOT NULL,
+    unit_price_cents BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_events (
+    id VARCHAR(255) PRIMARY KEY,
+    received_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    type VARCHAR(100) NOT NULL
+);
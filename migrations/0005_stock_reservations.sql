@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS stock_reservations (
+    cart_id UUID NOT NULL REFERENCES carts (id) ON DELETE CASCADE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    id SERIAL PRIMARY KEY,
+    payment_intent_id VARCHAR(255),
+    product_id INT NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    quantity INT NOT NULL CHECK (quantity > 0),
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'committed', 'released', 'canceled'))
+);
+
+CREATE INDEX IF NOT EXISTS stock_reservations_pending_idx
+ON stock_reservations (product_id) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS stock_reservations_payment_intent_idx
+ON stock_reservations (payment_intent_id);
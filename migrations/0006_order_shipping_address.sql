@@ -0,0 +1,7 @@
+ALTER TABLE orders
+ADD COLUMN IF NOT EXISTS shipping_city VARCHAR(100),
+ADD COLUMN IF NOT EXISTS shipping_country VARCHAR(2),
+ADD COLUMN IF NOT EXISTS shipping_line1 VARCHAR(200),
+ADD COLUMN IF NOT EXISTS shipping_line2 VARCHAR(200),
+ADD COLUMN IF NOT EXISTS shipping_postal_code VARCHAR(20),
+ADD COLUMN IF NOT EXISTS shipping_state VARCHAR(100);
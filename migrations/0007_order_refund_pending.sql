@@ -0,0 +1,5 @@
+-- Orders whose stock sold out before the payment went through stay
+-- `refund_pending` until the refund is made, so a failed refund is retried.
+ALTER TABLE orders DROP CONSTRAINT IF EXISTS orders_status_check;
+ALTER TABLE orders ADD CONSTRAINT orders_status_check
+    CHECK (status IN ('pending', 'paid', 'failed', 'refunded', 'refund_pending'));
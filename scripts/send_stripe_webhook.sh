@@ -0,0 +1,28 @@
+#!/usr/bin/env bash
+# Signs a fixture event like Stripe does and posts it to the local webhook.
+#
+# usage: scripts/send_stripe_webhook.sh fixtures/stripe/<event>.json <payment_intent_id> [url]
+#
+# Needs STRIPE_WEBHOOK_SECRET set to the same value as the server; the url
+# defaults to the webhook under PUBLIC_BASE_URL. Every send gets a fresh event
+# id, as the webhook acknowledges repeated ids without applying them again.
+set -euo pipefail
+
+fixture="${1:?usage: $0 <fixture> <payment_intent_id> [url]}"
+payment_intent_id="${2:?usage: $0 <fixture> <payment_intent_id> [url]}"
+url="${3:-${PUBLIC_BASE_URL:-http://localhost:8080}/stripe-webhook}"
+: "${STRIPE_WEBHOOK_SECRET:?STRIPE_WEBHOOK_SECRET must be set}"
+
+event_id="evt_local_$(date +%s)_$(openssl rand -hex 4)"
+payload="$(sed -e "s/{{EVENT_ID}}/${event_id}/g" \
+  -e "s/{{PAYMENT_INTENT_ID}}/${payment_intent_id}/g" "$fixture")"
+timestamp="$(date +%s)"
+signature="$(printf '%s.%s' "$timestamp" "$payload" |
+  openssl dgst -sha256 -hmac "$STRIPE_WEBHOOK_SECRET" | sed 's/^.* //')"
+
+curl --silent --show-error --fail-with-body \
+  --header "Content-Type: application/json" \
+  --header "Stripe-Signature: t=${timestamp},v1=${signature}" \
+  --data-binary "$payload" \
+  --write-out '%{http_code}\n' \
+  "$url"
@@ -0,0 +1,231 @@
+use crate::config::CatalogConfig;
+use crate::services::catalog::{self, ImportOptions, ParsedCatalog, RowError};
+use crate::utils;
+use clap::{Parser, Subcommand};
+use sqlx::{Pool, Postgres};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The command failed; the reason is printed to stderr.
+pub const EXIT_FAILURE: i32 = 1;
+/// `migrate --check` found pending migrations, or `serve` refused to start on them
+/// or failed to apply them.
+pub const EXIT_PENDING_MIGRATIONS: i32 = 3;
+/// `seed` or `import` rejected lines of the file; the report lists them.
+pub const EXIT_INVALID_ROWS: i32 = 4;
+/// The configuration is missing or malformed.
+pub const EXIT_CONFIG: i32 = 78;
+
+#[derive(Parser)]
+#[command(
+    name = "ecommerce",
+    about = "Online store server and catalog maintenance",
+    after_help = "Exit codes: 0 success, 1 failure, 2 usage error, 3 pending migrations, \
+        4 rejected CSV lines, 78 invalid configuration."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Apply pending database migrations
+    Migrate {
+        /// Only list pending migrations, exiting with 3 when there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Insert demo products, leaving existing ones untouched
+    Seed {
+        #[arg(long, default_value = "src/mock_data/products.csv")]
+        file: PathBuf,
+    },
+    /// Create or update products from a CSV file, matching columns by header
+    Import {
+        #[arg(long)]
+        file: PathBuf,
+        /// Report what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Write nothing when any line is rejected, instead of skipping it
+        #[arg(long)]
+        atomic: bool,
+        /// Also write the rejected lines to this file as CSV
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Write the catalog as CSV
+    Export {
+        /// Defaults to stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+async fn connect() -> Result<(CatalogConfig, Pool<Postgres>), i32> {
+    let config = CatalogConfig::load().map_err(|err| {
+        eprintln!("{}", err);
+        EXIT_CONFIG
+    })?;
+    let pool = utils::create_database_pool(&config.database)
+        .await
+        .map_err(|err| {
+            eprintln!("Error connecting to the database: {}", err);
+            EXIT_FAILURE
+        })?;
+    Ok((config, pool))
+}
+
+/// Prints the migrations the database is missing; returns whether there are any.
+pub fn report_pending(pending: &[&sqlx::migrate::Migration]) -> bool {
+    if pending.is_empty() {
+        return false;
+    }
+    eprintln!("The database is behind this build; pending migrations:");
+    for migration in pending {
+        eprintln!("  - {} {}", migration.version, migration.description);
+    }
+    true
+}
+
+/// Runs a maintenance command, returning the process exit code.
+pub async fn run(command: Command) -> i32 {
+    let (config, pool) = match connect().await {
+        Ok(connected) => connected,
+        Err(code) => return code,
+    };
+    let result = match command {
+        Command::Serve => unreachable!("`serve` is run by `main`"),
+        Command::Migrate { check } => migrate(&pool, check).await,
+        Command::Seed { file } => seed(&pool, &config, &file).await,
+        Command::Import {
+            file,
+            dry_run,
+            atomic,
+            report,
+        } => {
+            let options = ImportOptions { atomic, dry_run };
+            import(&pool, &config, &file, options, report.as_deref()).await
+        }
+        Command::Export { file } => export(&pool, &config, file).await,
+    };
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", err);
+            EXIT_FAILURE
+        }
+    }
+}
+
+type CommandResult = Result<i32, Box<dyn std::error::Error>>;
+
+fn read_catalog(file: &Path, config: &CatalogConfig) -> Result<ParsedCatalog, String> {
+    let reader =
+        File::open(file).map_err(|err| format!("Cannot open {}: {}", file.display(), err))?;
+    catalog::read_products(reader, config.store_currency.0)
+        .map_err(|err| format!("Cannot read {}: {}", file.display(), err))
+}
+
+fn print_errors(errors: &[RowError]) {
+    for error in errors {
+        match &error.column {
+            Some(column) => eprintln!(
+                "  line {}, column `{}`: {}",
+                error.line, column, error.reason
+            ),
+            None => eprintln!("  line {}: {}", error.line, error.reason),
+        }
+    }
+}
+
+async fn migrate(pool: &Pool<Postgres>, check: bool) -> CommandResult {
+    let pending = utils::pending_migrations(pool).await?;
+    if check {
+        return Ok(match report_pending(&pending) {
+            true => EXIT_PENDING_MIGRATIONS,
+            false => 0,
+        });
+    }
+    utils::MIGRATOR.run(pool).await?;
+    eprintln!("Applied {} migration(s)", pending.len());
+    Ok(0)
+}
+
+async fn seed(pool: &Pool<Postgres>, config: &CatalogConfig, file: &Path) -> CommandResult {
+    let catalog = read_catalog(file, config)?;
+    if !catalog.errors.is_empty() {
+        eprintln!("{} has invalid lines, nothing was seeded:", file.display());
+        print_errors(&catalog.errors);
+        return Ok(EXIT_INVALID_ROWS);
+    }
+    let products: Vec<_> = catalog
+        .products
+        .into_iter()
+        .map(|(_, product)| product)
+        .collect();
+    let inserted = catalog::seed(pool, &products).await?;
+    eprintln!(
+        "Inserted {} of {} product(s) from {}",
+        inserted,
+        products.len(),
+        file.display()
+    );
+    Ok(0)
+}
+
+async fn import(
+    pool: &Pool<Postgres>,
+    config: &CatalogConfig,
+    file: &Path,
+    options: ImportOptions,
+    report_file: Option<&Path>,
+) -> CommandResult {
+    let catalog = read_catalog(file, config)?;
+    let report = catalog::import(pool, catalog, options).await?;
+
+    eprintln!(
+        "{}: {} inserted, {} updated, {} line(s) rejected{}",
+        file.display(),
+        report.inserted,
+        report.updated,
+        report.errors.len(),
+        match (report.is_written, options.dry_run) {
+            (true, _) => "",
+            (false, true) => " (dry run, nothing was written)",
+            (false, false) => " (nothing was written)",
+        }
+    );
+    print_errors(&report.errors);
+    if let Some(report_file) = report_file {
+        let writer = File::create(report_file)
+            .map_err(|err| format!("Cannot create {}: {}", report_file.display(), err))?;
+        catalog::write_errors(&report.errors, writer)?;
+    }
+
+    Ok(match report.errors.is_empty() {
+        true => 0,
+        false => EXIT_INVALID_ROWS,
+    })
+}
+
+async fn export(
+    pool: &Pool<Postgres>,
+    config: &CatalogConfig,
+    file: Option<PathBuf>,
+) -> CommandResult {
+    let exported = match &file {
+        Some(file) => {
+            let writer = File::create(file)
+                .map_err(|err| format!("Cannot create {}: {}", file.display(), err))?;
+            catalog::export(pool, writer, config.store_currency.0).await?
+        }
+        None => catalog::export(pool, io::stdout().lock(), config.store_currency.0).await?,
+    };
+    eprintln!("Exported {} product(s)", exported);
+    Ok(0)
+}
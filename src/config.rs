@@ -0,0 +1,318 @@
+use crate::services::cart::CartCookieKey;
+use crate::utils::currency::StoreCurrency;
+use crate::utils::public_url::PublicUrl;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// TOML file read when `CONFIG_FILE` is not set, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Every setting, by environment variable. The config file uses the same
+/// names in lower case, e.g. `database_url = "postgres://..."`.
+const SETTINGS: &[&str] = &[
+    "AUTO_MIGRATE",
+    "BIND_ADDRESS",
+    "CART_COOKIE_SECRET",
+    "DATABASE_MAX_CONNECTIONS",
+    "DATABASE_URL",
+    "PAYMENT_PROVIDER",
+    "PUBLIC_BASE_URL",
+    "STORE_CURRENCY",
+    "STRIPE_PRIVATE_KEY",
+    "STRIPE_PUBLIC_KEY",
+    "STRIPE_WEBHOOK_SECRET",
+    "TEMPLATE_GLOB",
+];
+
+pub struct DatabaseConfig {
+    /// Apply pending migrations at startup instead of refusing to start.
+    pub auto_migrate: bool,
+    pub max_connections: u32,
+    pub url: String,
+}
+
+pub struct StripeConfig {
+    pub private_key: String,
+    pub public_key: String,
+    /// Secret the webhook's `Stripe-Signature` header is checked against.
+    pub webhook_secret: String,
+}
+
+/// Payment service provider behind the checkout, from `PAYMENT_PROVIDER`.
+pub enum PaymentConfig {
+    /// With a webhook secret, the webhook also accepts events signed with it,
+    /// so signed fixtures can drive orders offline.
+    Mock {
+        webhook_secret: Option<String>,
+    },
+    Stripe(StripeConfig),
+}
+
+/// Application settings, loaded once at startup and shared via `web::Data`.
+pub struct Config {
+    pub bind_address: String,
+    pub cart_cookie_key: CartCookieKey,
+    pub database: DatabaseConfig,
+    pub payment: PaymentConfig,
+    pub public_url: PublicUrl,
+    pub store_currency: StoreCurrency,
+    pub template_glob: String,
+}
+
+/// The settings the catalog and database commands need, so they run without
+/// the web server's secrets.
+pub struct CatalogConfig {
+    pub database: DatabaseConfig,
+    pub store_currency: StoreCurrency,
+}
+
+/// Every problem found while loading the config, so they can be fixed at once.
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Raw setting values, environment variables taking precedence over the file.
+struct Settings {
+    file: HashMap<String, String>,
+    problems: Vec<String>,
+}
+
+impl Settings {
+    fn load() -> Self {
+        let mut settings = Settings {
+            file: HashMap::new(),
+            problems: Vec::new(),
+        };
+        let path = match env::var("CONFIG_FILE") {
+            Ok(path) => path,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE.to_string(),
+            Err(_) => return settings,
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => settings.read_file(&path, &contents),
+            Err(err) => settings
+                .problems
+                .push(format!("Cannot read config file `{}`: {}", path, err)),
+        }
+        settings
+    }
+
+    fn read_file(&mut self, path: &str, contents: &str) {
+        let table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(err) => {
+                self.problems
+                    .push(format!("Cannot parse config file `{}`: {}", path, err));
+                return;
+            }
+        };
+        for (key, value) in table {
+            if !SETTINGS.contains(&key.to_uppercase().as_str()) || key != key.to_lowercase() {
+                self.problems
+                    .push(format!("Unknown setting `{}` in `{}`", key, path));
+                continue;
+            }
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => {
+                    self.problems.push(format!(
+                        "Setting `{}` in `{}` must be a string, an integer or a boolean",
+                        key, path
+                    ));
+                    continue;
+                }
+            };
+            self.file.insert(key, value);
+        }
+    }
+
+    fn optional(&self, name: &str) -> Option<String> {
+        env::var(name)
+            .ok()
+            .or_else(|| self.file.get(&name.to_lowercase()).cloned())
+    }
+
+    fn required(&mut self, name: &str) -> Option<String> {
+        let value = self.optional(name);
+        if value.is_none() {
+            self.problems.push(format!(
+                "Missing `{}` (env variable or `{}` in the config file)",
+                name,
+                name.to_lowercase()
+            ));
+        }
+        value
+    }
+
+    fn database(&mut self) -> Option<DatabaseConfig> {
+        let auto_migrate = match self.optional("AUTO_MIGRATE") {
+            Some(auto_migrate) => self.check("AUTO_MIGRATE", parse_bool(&auto_migrate)),
+            None => Some(false),
+        };
+        let max_connections = self
+            .optional("DATABASE_MAX_CONNECTIONS")
+            .unwrap_or_else(|| "5".to_string());
+        let max_connections = self.check(
+            "DATABASE_MAX_CONNECTIONS",
+            parse_max_connections(&max_connections),
+        );
+        let url = self.required("DATABASE_URL");
+        Some(DatabaseConfig {
+            auto_migrate: auto_migrate?,
+            max_connections: max_connections?,
+            url: url?,
+        })
+    }
+
+    fn store_currency(&mut self) -> Option<StoreCurrency> {
+        match self.optional("STORE_CURRENCY") {
+            Some(code) => self.check("STORE_CURRENCY", StoreCurrency::parse(&code)),
+            None => Some(StoreCurrency::default()),
+        }
+    }
+
+    /// Records a malformed value, prefixing the problem with the setting.
+    fn check<T>(&mut self, name: &str, result: Result<T, String>) -> Option<T> {
+        result
+            .map_err(|err| self.problems.push(format!("Invalid `{}`: {}", name, err)))
+            .ok()
+    }
+}
+
+fn parse_bind_address(bind_address: &str) -> Result<String, String> {
+    match bind_address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(bind_address.to_string())
+        }
+        _ => Err(format!("{} is not a `host:port` address", bind_address)),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(format!("{} is not `true` or `false`", value)),
+    }
+}
+
+fn parse_max_connections(max_connections: &str) -> Result<u32, String> {
+    match max_connections.parse::<u32>() {
+        Ok(max_connections) if max_connections > 0 => Ok(max_connections),
+        _ => Err(format!("{} is not a positive number", max_connections)),
+    }
+}
+
+impl Config {
+    /// Reads the settings from the environment and the optional config file
+    /// (`CONFIG_FILE`, or `config.toml` when present).
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut settings = Settings::load();
+
+        let database = settings.database();
+
+        let bind_address = settings
+            .optional("BIND_ADDRESS")
+            .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+        let bind_address = settings.check("BIND_ADDRESS", parse_bind_address(&bind_address));
+
+        let cart_cookie_key = settings
+            .required("CART_COOKIE_SECRET")
+            .and_then(|secret| settings.check("CART_COOKIE_SECRET", CartCookieKey::new(secret)));
+
+        let payment = match settings.optional("PAYMENT_PROVIDER").as_deref() {
+            Some("stripe") | None => {
+                let private_key = settings.required("STRIPE_PRIVATE_KEY");
+                let public_key = settings.required("STRIPE_PUBLIC_KEY");
+                let webhook_secret = settings.required("STRIPE_WEBHOOK_SECRET");
+                match (private_key, public_key, webhook_secret) {
+                    (Some(private_key), Some(public_key), Some(webhook_secret)) => {
+                        Some(PaymentConfig::Stripe(StripeConfig {
+                            private_key,
+                            public_key,
+                            webhook_secret,
+                        }))
+                    }
+                    _ => None,
+                }
+            }
+            Some("mock") => Some(PaymentConfig::Mock {
+                webhook_secret: settings.optional("STRIPE_WEBHOOK_SECRET"),
+            }),
+            Some(other) => settings.check(
+                "PAYMENT_PROVIDER",
+                Err(format!("{} (expected `stripe` or `mock`)", other)),
+            ),
+        };
+
+        let public_url = settings
+            .required("PUBLIC_BASE_URL")
+            .and_then(|base_url| settings.check("PUBLIC_BASE_URL", PublicUrl::parse(&base_url)));
+
+        let store_currency = settings.store_currency();
+
+        let template_glob = settings
+            .optional("TEMPLATE_GLOB")
+            .unwrap_or_else(|| "src/html/*".to_string());
+
+        match (
+            bind_address,
+            cart_cookie_key,
+            database,
+            payment,
+            public_url,
+            store_currency,
+        ) {
+            (
+                Some(bind_address),
+                Some(cart_cookie_key),
+                Some(database),
+                Some(payment),
+                Some(public_url),
+                Some(store_currency),
+            ) if settings.problems.is_empty() => Ok(Config {
+                bind_address,
+                cart_cookie_key,
+                database,
+                payment,
+                public_url,
+                store_currency,
+                template_glob,
+            }),
+            _ => Err(ConfigError(settings.problems)),
+        }
+    }
+}
+
+impl CatalogConfig {
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut settings = Settings::load();
+        let database = settings.database();
+        let store_currency = settings.store_currency();
+        match (database, store_currency) {
+            (Some(database), Some(store_currency)) if settings.problems.is_empty() => {
+                Ok(CatalogConfig {
+                    database,
+                    store_currency,
+                })
+            }
+            _ => Err(ConfigError(settings.problems)),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use super::home;
+use crate::errors::AppError;
+use crate::utils;
+use crate::utils::currency::CurrencyContext;
+use crate::utils::pagination::{PageQuery, Pagination};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use tera::{Context, Tera};
+
+#[derive(Serialize)]
+struct Category {
+    name: String,
+    product_count: i64,
+    slug: String,
+}
+
+fn map_row_to_category(row: &sqlx::postgres::PgRow) -> Result<Category, String> {
+    let name: String = row
+        .try_get("category")
+        .map_err(|_| "Error getting `category`")?;
+    let product_count: i64 = row
+        .try_get("product_count")
+        .map_err(|_| "Error getting `product_count`")?;
+    let slug: String = row
+        .try_get("category_slug")
+        .map_err(|_| "Error getting `category_slug`")?;
+
+    Ok(Category {
+        name,
+        product_count,
+        slug,
+    })
+}
+
+pub async fn index(
+    pool: web::Data<Pool<Postgres>>,
+    tmpl: web::Data<Tera>,
+) -> Result<HttpResponse, AppError> {
+    let query = "
+        SELECT category, category_slug, COUNT(*) AS product_count
+        FROM products
+        WHERE is_active AND category IS NOT NULL AND category_slug <> ''
+        GROUP BY category, category_slug
+        ORDER BY category;
+        ";
+    let rows = sqlx::query(query).fetch_all(pool.get_ref()).await?;
+
+    let categories: Vec<Category> = rows
+        .into_iter()
+        .filter_map(|row| map_row_to_category(&row).ok())
+        .collect();
+
+    let mut context = Context::new();
+    context.insert("title", "Ecommerce - Categories");
+    context.insert("categories", &categories);
+
+    utils::render_template(&tmpl, "categories.html", &context)
+}
+
+pub async fn handler(
+    pool: web::Data<Pool<Postgres>>,
+    req: HttpRequest,
+    tmpl: web::Data<Tera>,
+    path: web::Path<(String,)>,
+    query: web::Query<PageQuery>,
+    currency: CurrencyContext,
+) -> Result<HttpResponse, AppError> {
+    let slug = path.into_inner().0;
+
+    let query_category = "
+        SELECT category, COUNT(*) AS product_count
+        FROM products
+        WHERE is_active AND category_slug = $1
+        GROUP BY category;
+        ";
+    let row = sqlx::query(query_category)
+        .bind(&slug)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let category: String = row.try_get("category")?;
+    let total: i64 = row.try_get("product_count")?;
+
+    let query_products = "
+        SELECT id, name, description, price_cents, image_url
+        FROM products
+        WHERE is_active AND category_slug = $1
+        ORDER BY id
+        LIMIT $2 OFFSET $3;
+        ";
+    let rows = sqlx::query(query_products)
+        .bind(&slug)
+        .bind(query.per_page())
+        .bind(query.offset())
+        .fetch_all(pool.get_ref())
+        .await?;
+
+    let products: Vec<home::HomeProduct> = rows
+        .into_iter()
+        .filter_map(|row| home::map_row_to_product(&row, currency.store).ok())
+        .collect();
+
+    let base_url = format!("/category/{}", slug);
+
+    let mut context = Context::new();
+    context.insert("title", &category);
+    context.insert("currency", &currency);
+    context.insert("category", &category);
+    context.insert("products", &products);
+    context.insert("pagination", &Pagination::new(&query, total, &base_url));
+
+    if utils::is_htmx_request(&req) {
+        return utils::render_template(&tmpl, "_products.html", &context);
+    }
+    utils::render_template(&tmpl, "category.html", &context)
+}
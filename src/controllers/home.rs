@@ -1,19 +1,186 @@
+use crate::errors::AppError;
 use crate::utils;
-use actix_web::{web, HttpResponse, Responder};
-use serde::Serialize;
-use sqlx::{Pool, Postgres, Row};
+use crate::utils::currency::CurrencyContext;
+use crate::utils::money::Money;
+use crate::utils::pagination::{PageQuery, Pagination};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, QueryBuilder, Row};
+use stripe::Currency;
 use tera::{Context, Tera};
 
 #[derive(Serialize)]
-struct HomeProduct {
+pub(super) struct HomeProduct {
     description: String,
     id: i32,
     image_url: String,
     name: String,
-    price: f64,
+    price: Money,
 }
 
-fn map_row_to_product(row: &sqlx::postgres::PgRow) -> Result<HomeProduct, String> {
+#[derive(Serialize)]
+struct CategoryFacet {
+    name: String,
+    product_count: i64,
+    slug: String,
+}
+
+#[derive(Serialize)]
+struct Facets {
+    categories: Vec<CategoryFacet>,
+    in_stock_count: i64,
+}
+
+/// Which facet is being counted; its own filter is left out so every option
+/// shows how many products selecting it would yield.
+#[derive(Clone, Copy, PartialEq)]
+enum Facet {
+    Category,
+    InStock,
+}
+
+/// Catalog filters and sort order, kept as the raw query-string values so the
+/// form can echo them back and the pagination links can carry them along.
+#[derive(Default, Deserialize, Serialize)]
+pub struct CatalogFilter {
+    category: Option<String>,
+    in_stock: Option<String>,
+    max_price: Option<String>,
+    min_price: Option<String>,
+    sort: Option<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl CatalogFilter {
+    /// Drops empty and unparsable values so they neither reach SQL nor the URL.
+    fn normalized(&self, store: Currency) -> CatalogFilter {
+        let price = |value: &Option<String>| {
+            non_empty(value)
+                .and_then(|v| Money::parse(v, store).ok())
+                .map(|v| v.to_string())
+        };
+        CatalogFilter {
+            category: non_empty(&self.category).map(str::to_string),
+            in_stock: self.in_stock().then(|| "true".to_string()),
+            max_price: price(&self.max_price),
+            min_price: price(&self.min_price),
+            sort: non_empty(&self.sort)
+                .filter(|sort| Self::order_by(sort).is_some())
+                .map(str::to_string),
+        }
+    }
+
+    fn in_stock(&self) -> bool {
+        matches!(non_empty(&self.in_stock), Some("true" | "on" | "1"))
+    }
+
+    fn order_by(sort: &str) -> Option<&'static str> {
+        match sort {
+            "name" => Some("name ASC, id"),
+            "newest" => Some("created_at DESC, id DESC"),
+            "price_asc" => Some("price_cents ASC, id"),
+            "price_desc" => Some("price_cents DESC, id"),
+            _ => None,
+        }
+    }
+
+    fn push_conditions(
+        &self,
+        builder: &mut QueryBuilder<'_, Postgres>,
+        store: Currency,
+        facet: Option<Facet>,
+    ) {
+        builder.push(" WHERE is_active");
+        if let Some(category) = non_empty(&self.category) {
+            if facet != Some(Facet::Category) {
+                builder
+                    .push(" AND category_slug = ")
+                    .push_bind(category.to_string());
+            }
+        }
+        if self.in_stock() && facet != Some(Facet::InStock) {
+            builder.push(" AND stock_quantity > 0");
+        }
+        let price =
+            |value: &Option<String>| non_empty(value).and_then(|v| Money::parse(v, store).ok());
+        if let Some(min_price) = price(&self.min_price) {
+            builder
+                .push(" AND price_cents >= ")
+                .push_bind(min_price.minor_units());
+        }
+        if let Some(max_price) = price(&self.max_price) {
+            builder
+                .push(" AND price_cents <= ")
+                .push_bind(max_price.minor_units());
+        }
+    }
+
+    fn push_order_by(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        let order_by = non_empty(&self.sort)
+            .and_then(Self::order_by)
+            .unwrap_or("id");
+        builder.push(" ORDER BY ").push(order_by);
+    }
+}
+
+fn map_row_to_category_facet(row: &sqlx::postgres::PgRow) -> Result<CategoryFacet, String> {
+    let name: String = row
+        .try_get("category")
+        .map_err(|_| "Error getting `category`")?;
+    let product_count: i64 = row
+        .try_get("product_count")
+        .map_err(|_| "Error getting `product_count`")?;
+    let slug: String = row
+        .try_get("category_slug")
+        .map_err(|_| "Error getting `category_slug`")?;
+
+    Ok(CategoryFacet {
+        name,
+        product_count,
+        slug,
+    })
+}
+
+async fn fetch_facets(
+    pool: &Pool<Postgres>,
+    filter: &CatalogFilter,
+    store: Currency,
+) -> Result<Facets, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "SELECT category, category_slug, COUNT(*) AS product_count FROM products",
+    );
+    filter.push_conditions(&mut builder, store, Some(Facet::Category));
+    builder.push(
+        " AND category IS NOT NULL AND category_slug <> ''
+        GROUP BY category, category_slug
+        ORDER BY category",
+    );
+    let categories = builder
+        .build()
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .filter_map(|row| map_row_to_category_facet(&row).ok())
+        .collect();
+
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM products");
+    filter.push_conditions(&mut builder, store, Some(Facet::InStock));
+    builder.push(" AND stock_quantity > 0");
+    let in_stock_count: i64 = builder.build_query_scalar().fetch_one(pool).await?;
+
+    Ok(Facets {
+        categories,
+        in_stock_count,
+    })
+}
+
+pub(super) fn map_row_to_product(
+    row: &sqlx::postgres::PgRow,
+    store: Currency,
+) -> Result<HomeProduct, String> {
     let description: String = row
         .try_get("description")
         .map_err(|_| "Error getting `description`")?;
@@ -22,10 +189,10 @@ fn map_row_to_product(row: &sqlx::postgres::PgRow) -> Result<HomeProduct, String
         .try_get("image_url")
         .map_err(|_| "Error getting `image_url`")?;
     let name: String = row.try_get("name").map_err(|_| "Error getting `name`")?;
-    let price: f64 = row
-        .try_get("price")
-        .map(utils::round_price)
-        .map_err(|_| "Error getting `price`")?;
+    let price = row
+        .try_get("price_cents")
+        .map(|cents| Money::new(cents, store))
+        .map_err(|_| "Error getting `price_cents`")?;
 
     Ok(HomeProduct {
         description,
@@ -36,24 +203,58 @@ fn map_row_to_product(row: &sqlx::postgres::PgRow) -> Result<HomeProduct, String
     })
 }
 
-pub async fn handler(pool: web::Data<Pool<Postgres>>, tmpl: web::Data<Tera>) -> impl Responder {
-    let query = "SELECT id, name, description, price, image_url FROM products;";
-    let rows = match sqlx::query(query).fetch_all(pool.get_ref()).await {
-        Ok(rows) => rows,
-        Err(err) => {
-            eprintln!("Database query error: {:#?}", err);
-            return HttpResponse::InternalServerError().body("Internal Server Error");
-        }
-    };
+pub async fn handler(
+    pool: web::Data<Pool<Postgres>>,
+    req: HttpRequest,
+    tmpl: web::Data<Tera>,
+    query: web::Query<PageQuery>,
+    filter: web::Query<CatalogFilter>,
+    currency: CurrencyContext,
+) -> Result<HttpResponse, AppError> {
+    let filter = filter.normalized(currency.store);
+
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM products");
+    filter.push_conditions(&mut builder, currency.store, None);
+    let total: i64 = builder
+        .build_query_scalar()
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let mut builder =
+        QueryBuilder::new("SELECT id, name, description, price_cents, image_url FROM products");
+    filter.push_conditions(&mut builder, currency.store, None);
+    filter.push_order_by(&mut builder);
+    builder
+        .push(" LIMIT ")
+        .push_bind(query.per_page())
+        .push(" OFFSET ")
+        .push_bind(query.offset());
+    let rows = builder.build().fetch_all(pool.get_ref()).await?;
 
     let products: Vec<HomeProduct> = rows
         .into_iter()
-        .filter_map(|row| map_row_to_product(&row).ok())
+        .filter_map(|row| map_row_to_product(&row, currency.store).ok())
         .collect();
 
+    let base_url = match serde_urlencoded::to_string(&filter) {
+        Ok(encoded) if encoded.is_empty() => "/".to_string(),
+        Ok(encoded) => format!("/?{}", encoded),
+        Err(_) => return Err(AppError::Validation("Invalid catalog filter".to_string())),
+    };
+
     let mut context = Context::new();
     context.insert("title", "Ecommerce");
+    context.insert("currency", &currency);
     context.insert("products", &products);
+    context.insert("pagination", &Pagination::new(&query, total, &base_url));
+
+    if utils::is_htmx_request(&req) {
+        return utils::render_template(&tmpl, "_products.html", &context);
+    }
+
+    let facets = fetch_facets(pool.get_ref(), &filter, currency.store).await?;
+    context.insert("facets", &facets);
+    context.insert("filter", &filter);
 
     utils::render_template(&tmpl, "index.html", &context)
 }
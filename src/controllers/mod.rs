@@ -1,283 +1,392 @@
 pub mod cart;
+pub mod categories;
 pub mod home;
+pub mod order_details;
 pub mod product_details;
+pub mod search;
+pub mod webhook;
 
+use crate::errors::AppError;
+use crate::services;
+use crate::services::cart::CartSession;
+use crate::services::payments::mock_provider::MockPaymentProvider;
+use crate::services::payments::{IntentStatus, NewIntent, PaymentEvent, PaymentProvider};
+use crate::utils::currency::{CurrencyContext, DISPLAY_CURRENCY_COOKIE};
 use actix_web::cookie::{time::Duration, CookieBuilder, SameSite};
-use actix_web::http::header::HeaderValue;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use futures::TryStreamExt;
-use serde::Serialize;
-use sqlx::{Pool, Postgres, Row};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::{StatusCode, Uri};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
 use std::collections::HashMap;
-use std::env;
-use stripe::{CreatePaymentIntent, Currency, PaymentIntent};
 use tera::{Context, Tera};
 
-#[derive(Serialize)]
-struct CartProduct {
-    id: i32,
-    name: String,
-    price: f64,
-    quantity: i32,
-    total_price_item: f64,
-}
-
 pub async fn add_to_cart(
+    pool: web::Data<Pool<Postgres>>,
     path: web::Path<(i32,)>,
-    req: HttpRequest,
+    cart: CartSession,
     form: web::Form<HashMap<String, String>>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     let id = path.into_inner().0;
+
+    // Stock held by other carts' checkouts can't be bought either.
+    let query = format!(
+        "SELECT is_active, {} FROM products p WHERE p.id = $2",
+        services::inventory::AVAILABLE_STOCK
+    );
+    let (is_active, stock_quantity): (Option<bool>, i32) = sqlx::query_as(&query)
+        .bind(cart.id.as_deref())
+        .bind(id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if is_active != Some(true) {
+        return Err(AppError::Validation(
+            "Product is no longer available".to_string(),
+        ));
+    }
+
     let quantity: i32 = form
         .get("quantity")
         .and_then(|q| q.parse::<i32>().ok())
-        .map(|q| q.clamp(1, 100))
+        .map(|q| q.clamp(1, services::cart::MAX_QUANTITY))
         .unwrap_or(1);
-
-    let mut cart: HashMap<i32, i32> = HashMap::new();
-
-    if let Some(cookie) = req.cookie("cart") {
-        for item in cookie.value().split(',') {
-            if let Some((id_str, qty_str)) = item.split_once(':') {
-                if let (Ok(id), Ok(qty)) = (id_str.parse::<i32>(), qty_str.parse::<i32>()) {
-                    cart.insert(id, qty);
-                }
-            }
-        }
+    if stock_quantity <= 0 {
+        return Err(AppError::Validation("Product is out of stock".to_string()));
+    }
+    if quantity > stock_quantity {
+        return Err(AppError::Validation(format!(
+            "Only {} left in stock",
+            stock_quantity
+        )));
     }
 
-    cart.entry(id)
-        .and_modify(|q| *q = quantity)
-        .or_insert(quantity);
-
-    let cart_value = cart
-        .into_iter()
-        .map(|(id, qty)| format!("{}:{}", id, qty))
-        .collect::<Vec<String>>()
-        .join(",");
-
-    let cookie = CookieBuilder::new("cart", cart_value)
-        .path("/")
-        .secure(true)
-        .http_only(true)
-        .same_site(SameSite::Strict)
-        .max_age(Duration::weeks(1))
-        .finish();
+    let cart_id = services::cart::ensure_cart(pool.get_ref(), cart.id.clone()).await?;
+    services::cart::set_quantity(pool.get_ref(), &cart_id, id, quantity).await?;
 
-    HttpResponse::SeeOther()
+    Ok(HttpResponse::SeeOther()
         .insert_header(("HX-Redirect", HeaderValue::from_static("/cart")))
-        .cookie(cookie)
-        .finish()
+        .cookie(cart.cookie(&cart_id))
+        .finish())
 }
 
-pub async fn remove_from_cart(path: web::Path<(i32,)>, req: HttpRequest) -> impl Responder {
+pub async fn remove_from_cart(
+    pool: web::Data<Pool<Postgres>>,
+    path: web::Path<(i32,)>,
+    cart: CartSession,
+) -> Result<HttpResponse, AppError> {
     let id_to_remove = path.into_inner().0;
-    let mut cart: HashMap<i32, i32> = HashMap::new();
-    if let Some(cookie) = req.cookie("cart") {
-        for item in cookie.value().split(',') {
-            if let Some((id_str, qty_str)) = item.split_once(':') {
-                if let (Ok(id), Ok(qty)) = (id_str.parse::<i32>(), qty_str.parse::<i32>()) {
-                    if id != id_to_remove {
-                        cart.insert(id, qty);
-                    }
-                }
-            }
-        }
+    if let Some(cart_id) = &cart.id {
+        services::cart::remove_item(pool.get_ref(), cart_id, id_to_remove).await?;
     }
-    let cart_value = cart
-        .into_iter()
-        .map(|(id, qty)| format!("{}:{}", id, qty))
-        .collect::<Vec<String>>()
-        .join(",");
 
-    let cookie = CookieBuilder::new("cart", cart_value)
+    // Carries the migrated cart's cookie, or clears an invalid one, like
+    // every other cart change.
+    Ok(cart
+        .response()
+        .status(StatusCode::GONE)
+        .insert_header(("Location", "/cart"))
+        .insert_header(("HX-Refresh", "true"))
+        .finish())
+}
+
+pub async fn set_display_currency(
+    req: HttpRequest,
+    form: web::Form<HashMap<String, String>>,
+) -> Result<HttpResponse, AppError> {
+    let code = form
+        .get("currency")
+        .map(|code| code.trim().to_uppercase())
+        .filter(|code| code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()))
+        .ok_or_else(|| AppError::Validation("Invalid currency".to_string()))?;
+
+    let cookie = CookieBuilder::new(DISPLAY_CURRENCY_COOKIE, code)
         .path("/")
         .secure(true)
         .http_only(true)
         .same_site(SameSite::Strict)
-        .max_age(Duration::weeks(1))
+        .max_age(Duration::weeks(52))
         .finish();
-    HttpResponse::Gone()
-        .insert_header(("Location", "/cart"))
-        .insert_header(("HX-Refresh", "true"))
+
+    let back = req
+        .headers()
+        .get(header::REFERER)
+        .and_then(|referer| referer.to_str().ok())
+        .and_then(|referer| referer.parse::<Uri>().ok())
+        .and_then(|uri| uri.path_and_query().map(|path| path.to_string()))
+        .unwrap_or_else(|| "/".to_string());
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, back))
         .cookie(cookie)
-        .finish()
+        .finish())
 }
 
-pub async fn not_found(tmpl: web::Data<Tera>) -> impl Responder {
-    let mut context = Context::new();
-    context.insert("title", "Ecommerce - Not Found");
-
-    match tmpl.render("404.html", &context) {
-        Ok(rendered) => HttpResponse::Ok().body(rendered),
-        Err(err) => {
-            eprintln!("{:#?}", err);
-            HttpResponse::InternalServerError().body("Error rendering template")
-        }
-    }
+pub async fn not_found() -> Result<HttpResponse, AppError> {
+    Err(AppError::NotFound)
 }
 
 pub async fn payment(
     pool: web::Data<Pool<Postgres>>,
     tmpl: web::Data<Tera>,
-    req: HttpRequest,
-) -> impl Responder {
-    let cart_cookie = match req.cookie("cart") {
-        Some(cookie) if !cookie.value().is_empty() => cookie,
+    provider: web::Data<dyn PaymentProvider>,
+    cart: CartSession,
+    currency: CurrencyContext,
+) -> Result<HttpResponse, AppError> {
+    let products = match &cart.id {
+        Some(cart_id) => {
+            // Lines that became unavailable since they were added are adjusted
+            // before charging, and the customer reviews the cart again.
+            if services::cart::fit_to_stock(pool.get_ref(), cart_id).await? > 0 {
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((header::LOCATION, "/cart?adjusted=true"))
+                    .finish());
+            }
+            services::cart::lines(pool.get_ref(), cart_id, currency.store).await?
+        }
+        None => Vec::new(),
+    };
+
+    let cart_id = match &cart.id {
+        Some(cart_id) if !products.is_empty() => cart_id,
         _ => {
             let mut context = Context::new();
             context.insert("title", "Payment");
-            return match tmpl.render("empty_cart.html", &context) {
-                Ok(rendered) => HttpResponse::Ok().body(rendered),
-                Err(err) => {
-                    eprintln!("Error rendering empty cart template: {:?}", err);
-                    HttpResponse::InternalServerError().body("Error rendering template")
-                }
-            };
+            return Ok(cart
+                .response()
+                .body(tmpl.render("empty_cart.html", &context)?));
         }
     };
 
-    let mut cart_items: HashMap<i32, i32> = HashMap::new();
-    for product in cart_cookie.value().split(',') {
-        if let Some((id_str, quantity_str)) = product.split_once(':') {
-            let id = match id_str.parse::<i32>() {
-                Ok(id) => id,
-                Err(err) => {
-                    eprintln!("Error parsing product ID: {:?}", err);
-                    return HttpResponse::InternalServerError().finish();
-                }
-            };
-            let quantity = match quantity_str.parse::<i32>() {
-                Ok(quantity) => quantity,
-                Err(err) => {
-                    eprintln!("Error parsing product quantity: {:?}", err);
-                    return HttpResponse::InternalServerError().finish();
-                }
-            };
-            cart_items.insert(id, quantity);
-        } else {
-            eprintln!("Error splitting product entry: {}", product);
-            return HttpResponse::InternalServerError().finish();
-        }
+    if !services::inventory::reserve(pool.get_ref(), cart_id).await? {
+        return Err(AppError::Validation(
+            "Some items in your cart are held by other checkouts, please try again in a few minutes"
+                .to_string(),
+        ));
     }
 
-    let product_ids: Vec<i32> = cart_items.keys().cloned().collect();
-    let mut description: String = String::new();
-    let mut products = Vec::new();
-    let mut total_price: f64 = 0.0;
-
-    let mut rows = sqlx::query("SELECT id, name, price FROM products WHERE id = ANY($1)")
-        .bind(&product_ids)
-        .fetch(pool.get_ref());
-
-    while let Some(row) = rows.try_next().await.unwrap_or_else(|error| {
-        eprintln!("Database query error: {:?}", error);
-        None
-    }) {
-        let id: i32 = row.try_get("id").unwrap_or_default();
-        let name: String = row.try_get("name").unwrap_or_default();
-        let price: f64 = match row.try_get::<f64, _>("price") {
-            Ok(value) => (value * 100.0).round() / 100.0,
-            Err(error) => {
-                eprintln!("Error retrieving price for product {}: {:?}", id, error);
-                continue;
-            }
-        };
-        if let Some(&quantity) = cart_items.get(&id) {
-            let total_price_item = price * quantity as f64;
-            total_price += total_price_item;
-            products.push(CartProduct {
-                id,
-                name,
-                price,
-                quantity,
-                total_price_item,
-            });
-        }
-    }
+    let total_price = services::cart::total(&products, currency.store);
 
-    total_price = (total_price * 100.0).round() / 100.0;
+    let description = products
+        .iter()
+        .map(|product| format!("{} (x{})", product.name, product.quantity))
+        .collect::<Vec<String>>()
+        .join(" + ");
 
-    let stripe_private_key = match env::var("STRIPE_PRIVATE_KEY") {
-        Ok(key) => key,
-        Err(_) => {
-            eprintln!("Error: Missing `STRIPE_PRIVATE_KEY` environment variable");
-            return HttpResponse::InternalServerError().body("Missing Stripe private key");
-        }
+    // Reuse the cart's open intent, so refreshing the page doesn't create new ones.
+    // One the provider no longer knows, e.g. a mock intent from before a
+    // restart, is replaced rather than failing the checkout.
+    let open_order = services::orders::find_open_for_cart(pool.get_ref(), cart_id).await?;
+    let open_intent = match &open_order {
+        Some(order) => match provider.retrieve_intent(&order.payment_intent_id).await {
+            Ok(intent) => intent.status.is_open().then_some(intent),
+            Err(err) => {
+                eprintln!(
+                    "[payment] Replacing PaymentIntent {}: {}",
+                    order.payment_intent_id, err
+                );
+                None
+            }
+        },
+        None => None,
     };
-    let stripe_public_key = match env::var("STRIPE_PUBLIC_KEY") {
-        Ok(key) => key,
-        Err(_) => {
-            eprintln!("Error: Missing `STRIPE_PUBLIC_KEY` environment variable");
-            return HttpResponse::InternalServerError().body("Missing Stripe public key");
-        }
+    let mut new_intent = NewIntent {
+        amount: total_price,
+        description: &description,
+        idempotency_key: String::new(),
     };
-
-    let client = stripe::Client::new(stripe_private_key);
-
-    let client_secret = {
-        let mut create_intent =
-            CreatePaymentIntent::new((total_price * 100.0) as i64, Currency::EUR);
-        create_intent.confirm = Some(false);
-        // create_intent.return_url = Some("http://localhost:8080/stripe-webhook");
-        // create_intent.shipping = Some(&shipping);
-
-        let mut description_vec: Vec<String> = Vec::new();
-        for product in &products {
-            let product_info = format!("{} (x{})", product.name, product.quantity);
-            description_vec.push(product_info);
-        }
-        description = description_vec.join(" + ");
-        create_intent.description = Some(&description);
-
-        match PaymentIntent::create(&client, create_intent).await {
-            Ok(payment_intent) => match payment_intent.client_secret {
-                Some(secret) => secret,
-                None => {
-                    eprintln!("No client secret found in payment intent");
-                    return HttpResponse::InternalServerError().body("Error rendering template");
-                }
-            },
-            Err(error) => {
-                eprintln!("Failed to create payment intent: {:?}", error);
-                return HttpResponse::InternalServerError().body("Error rendering template");
-            }
+    let payment_intent = match open_intent {
+        Some(intent) => provider.update_intent(&intent.id, new_intent).await?,
+        None => {
+            let attempt = services::orders::count_for_cart(pool.get_ref(), cart_id).await?;
+            new_intent.idempotency_key =
+                services::payments::idempotency_key(cart_id, attempt, &new_intent);
+            provider.create_intent(new_intent).await?
         }
     };
+    services::inventory::attach_payment_intent(pool.get_ref(), cart_id, &payment_intent.id).await?;
+    services::orders::save(
+        pool.get_ref(),
+        cart_id,
+        &products,
+        total_price,
+        &payment_intent.id,
+    )
+    .await?;
+    let client_secret = payment_intent
+        .client_secret
+        .ok_or_else(|| AppError::Payment("No client secret found in payment intent".to_string()))?;
 
     let mut context = Context::new();
     context.insert("CLIENT_SECRET", &client_secret);
+    let (customer, shipping) = match open_order {
+        Some(order) => (order.customer, order.shipping),
+        None => (None, None),
+    };
+    context.insert("countries", services::shipping::COUNTRIES);
+    context.insert("customer", &customer);
+    context.insert("shipping", &shipping);
     context.insert("description", &description);
-    context.insert("STRIPE_PUBLIC_KEY", &stripe_public_key);
+    context.insert("PAYMENT_INTENT_ID", &payment_intent.id);
+    context.insert("PAYMENT_PROVIDER", provider.name());
+    context.insert("STRIPE_PUBLIC_KEY", provider.public_key());
     context.insert("title", "Ecommerce - Payment");
+    context.insert("currency", &currency);
     context.insert("total_price", &total_price);
 
-    match tmpl.render("payment.html", &context) {
-        Ok(rendered) => HttpResponse::Ok().body(rendered),
-        Err(err) => {
-            eprintln!("Template rendering error: {:?}", err);
-            HttpResponse::InternalServerError().body("Error rendering template")
-        }
+    Ok(cart.response().body(tmpl.render("payment.html", &context)?))
+}
+
+#[derive(Deserialize)]
+pub struct CheckoutForm {
+    customer_email: String,
+    customer_fullname: String,
+    customer_phone: String,
+    payment_intent: String,
+    shipping_city: String,
+    shipping_country: String,
+    shipping_line1: String,
+    #[serde(default)]
+    shipping_line2: String,
+    #[serde(default)]
+    shipping_postal_code: String,
+    #[serde(default)]
+    shipping_state: String,
+}
+
+/// Saves the customer's contact details and shipping address on the
+/// checkout's order and its PaymentIntent. The payment page posts here
+/// before confirming the payment.
+pub async fn payment_details(
+    pool: web::Data<Pool<Postgres>>,
+    provider: web::Data<dyn PaymentProvider>,
+    cart: CartSession,
+    form: web::Form<CheckoutForm>,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let order = services::orders::find_by_payment_intent(pool.get_ref(), &form.payment_intent)
+        .await?
+        .filter(|order| order.cart_id.is_some() && order.cart_id == cart.id)
+        .ok_or(AppError::NotFound)?;
+
+    let contact = services::orders::Contact::parse(
+        &form.customer_fullname,
+        &form.customer_email,
+        &form.customer_phone,
+    )
+    .map_err(AppError::Validation)?;
+    let shipping = services::shipping::Address::parse(
+        &form.shipping_country,
+        &form.shipping_line1,
+        &form.shipping_line2,
+        &form.shipping_city,
+        &form.shipping_state,
+        &form.shipping_postal_code,
+    )
+    .map_err(AppError::Validation)?;
+
+    let is_open = services::orders::set_customer(
+        pool.get_ref(),
+        &order.payment_intent_id,
+        &contact,
+        &shipping,
+    )
+    .await?;
+    if !is_open {
+        return Err(AppError::Validation(
+            "This order has already been paid".to_string(),
+        ));
     }
+    provider
+        .set_customer(&order.payment_intent_id, &contact, &shipping)
+        .await?;
+
+    Ok(cart.response().finish())
 }
 
-pub async fn stripe_webhook(tmpl: web::Data<Tera>) -> impl Responder {
-    let cookie = CookieBuilder::new("cart", "")
-        .path("/")
-        .secure(true)
-        .http_only(true)
-        .same_site(SameSite::Strict)
-        .max_age(Duration::weeks(1))
-        .finish();
+#[derive(Deserialize)]
+pub struct PaymentReturnQuery {
+    payment_intent: Option<String>,
+}
 
-    let mut context = Context::new();
-    context.insert("title", "Thank You!");
+/// The payment provider redirects here after confirming a payment, with the
+/// PaymentIntent id.
+pub async fn payment_complete(
+    pool: web::Data<Pool<Postgres>>,
+    tmpl: web::Data<Tera>,
+    provider: web::Data<dyn PaymentProvider>,
+    cart: CartSession,
+    query: web::Query<PaymentReturnQuery>,
+) -> Result<HttpResponse, AppError> {
+    let payment_intent_id = query
+        .payment_intent
+        .as_deref()
+        .ok_or_else(|| AppError::Validation("Missing `payment_intent`".to_string()))?;
+    let payment_intent = provider.retrieve_intent(payment_intent_id).await?;
+
+    let is_own_order = services::orders::find_by_payment_intent(pool.get_ref(), &payment_intent.id)
+        .await?
+        .is_some_and(|order| order.cart_id.is_some() && order.cart_id == cart.id);
 
-    match tmpl.render("stripe-webhook.html", &context) {
-        Ok(rendered) => HttpResponse::Ok().cookie(cookie).body(rendered),
-        Err(err) => {
-            eprintln!("Template rendering error: {:?}", err);
-            HttpResponse::InternalServerError().body("Error rendering template")
+    let (outcome, title) = match payment_intent.status {
+        IntentStatus::Succeeded => {
+            let event = PaymentEvent::Succeeded(payment_intent.id.clone());
+            services::payments::apply_event(pool.get_ref(), provider.get_ref(), &event).await?;
+            // Only the checkout's own cart is cleared and linked to the order.
+            if let (Some(cart_id), true) = (&cart.id, is_own_order) {
+                services::cart::clear(pool.get_ref(), cart_id).await?;
+            }
+            ("succeeded", "Thank You!")
         }
-    }
+        IntentStatus::Processing => ("processing", "Payment Processing"),
+        IntentStatus::Canceled | IntentStatus::Failed => {
+            let event = PaymentEvent::Failed(payment_intent.id.clone());
+            services::payments::apply_event(pool.get_ref(), provider.get_ref(), &event).await?;
+            ("failed", "Payment Failed")
+        }
+        IntentStatus::Incomplete => ("failed", "Payment Not Completed"),
+    };
+    let order = match is_own_order {
+        true => {
+            services::orders::find_by_payment_intent(pool.get_ref(), &payment_intent.id).await?
+        }
+        false => None,
+    };
+
+    let mut context = Context::new();
+    context.insert("title", title);
+    context.insert("outcome", outcome);
+    context.insert("order", &order);
+
+    Ok(cart
+        .response()
+        .body(tmpl.render("payment_complete.html", &context)?))
+}
+
+/// Test-mode payment form target, only available with the mock provider.
+/// Settles the intent, applies the event the webhook would have delivered and
+/// follows the same return page as a real payment.
+pub async fn mock_payment(
+    pool: web::Data<Pool<Postgres>>,
+    provider: web::Data<dyn PaymentProvider>,
+    mock: Option<web::Data<MockPaymentProvider>>,
+    form: web::Form<HashMap<String, String>>,
+) -> Result<HttpResponse, AppError> {
+    let mock = mock.ok_or(AppError::NotFound)?;
+    let payment_intent_id = form
+        .get("payment_intent")
+        .ok_or_else(|| AppError::Validation("Missing `payment_intent`".to_string()))?;
+    let succeed = form.get("outcome").map(String::as_str) == Some("succeed");
+
+    let event = mock.confirm(payment_intent_id, succeed)?;
+    services::payments::apply_event(pool.get_ref(), provider.get_ref(), &event).await?;
+
+    let location = format!(
+        "/payment/complete?{}",
+        serde_urlencoded::to_string([("payment_intent", payment_intent_id)])
+            .map_err(|err| AppError::Payment(err.to_string()))?
+    );
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location))
+        .finish())
 }
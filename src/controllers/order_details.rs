@@ -0,0 +1,31 @@
+use crate::errors::AppError;
+use crate::services;
+use crate::services::cart::CartSession;
+use crate::utils;
+use crate::utils::currency::CurrencyContext;
+use actix_web::{web, HttpResponse};
+use sqlx::{Pool, Postgres};
+use tera::{Context, Tera};
+
+pub async fn handler(
+    pool: web::Data<Pool<Postgres>>,
+    tmpl: web::Data<Tera>,
+    path: web::Path<(i32,)>,
+    cart: CartSession,
+    currency: CurrencyContext,
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner().0;
+
+    // Orders are only shown to the cart that placed them.
+    let order = services::orders::find(pool.get_ref(), id)
+        .await?
+        .filter(|order| order.cart_id.is_some() && order.cart_id == cart.id)
+        .ok_or(AppError::NotFound)?;
+
+    let mut context = Context::new();
+    context.insert("title", &format!("Order #{}", order.id));
+    context.insert("currency", &currency);
+    context.insert("order", &order);
+
+    utils::render_template(&tmpl, "order.html", &context)
+}
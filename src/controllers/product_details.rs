@@ -1,46 +1,42 @@
+use crate::errors::AppError;
 use crate::utils;
-use actix_web::{web, HttpResponse, Responder};
+use crate::utils::currency::CurrencyContext;
+use crate::utils::money::Money;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
 use serde::Serialize;
 use sqlx::{Pool, Postgres, Row};
+use stripe::Currency;
 use tera::{Context, Tera};
 
 #[derive(Serialize)]
 struct DetailsProduct {
     category: String,
+    category_slug: String,
     description: String,
     id: i32,
     image_url: String,
     name: String,
-    price: f64,
+    price: Money,
     stock_quantity: i32,
 }
 
-async fn map_row_to_product(row: sqlx::postgres::PgRow) -> Result<DetailsProduct, String> {
-    let category: String = row
-        .try_get("category")
-        .map_err(|_| "Error getting `category`".to_string())?;
-    let description: String = row
-        .try_get("description")
-        .map_err(|_| "Error getting `description`".to_string())?;
-    let id: i32 = row
-        .try_get("id")
-        .map_err(|_| "Error getting `id`".to_string())?;
-    let image_url: String = row
-        .try_get("image_url")
-        .map_err(|_| "Error getting `image_url`".to_string())?;
-    let name: String = row
-        .try_get("name")
-        .map_err(|_| "Error getting `name`".to_string())?;
-    let price: f64 = row
-        .try_get::<f64, _>("price")
-        .map(utils::round_price)
-        .map_err(|_| "Error getting `price`".to_string())?;
-    let stock_quantity: i32 = row
-        .try_get("stock_quantity")
-        .map_err(|_| "Error getting `stock_quantity`".to_string())?;
+async fn map_row_to_product(
+    row: sqlx::postgres::PgRow,
+    store: Currency,
+) -> Result<DetailsProduct, sqlx::Error> {
+    let category: String = row.try_get("category")?;
+    let category_slug: String = row.try_get("category_slug")?;
+    let description: String = row.try_get("description")?;
+    let id: i32 = row.try_get("id")?;
+    let image_url: String = row.try_get("image_url")?;
+    let name: String = row.try_get("name")?;
+    let price = Money::new(row.try_get("price_cents")?, store);
+    let stock_quantity: i32 = row.try_get("stock_quantity")?;
 
     Ok(DetailsProduct {
         category,
+        category_slug,
         description,
         id,
         image_url,
@@ -54,33 +50,43 @@ pub async fn handler(
     pool: web::Data<Pool<Postgres>>,
     tmpl: web::Data<Tera>,
     path: web::Path<(i32,)>,
-) -> impl Responder {
+    currency: CurrencyContext,
+) -> Result<HttpResponse, AppError> {
     let id = path.into_inner().0;
 
     let query = "
-        SELECT id, name, description, price, stock_quantity, category, image_url
+        SELECT id, name, description, price_cents, stock_quantity, category, category_slug, image_url, is_active
         FROM products
         WHERE id = $1;
         ";
 
-    let row = match sqlx::query(query).bind(id).fetch_one(pool.get_ref()).await {
-        Ok(row) => row,
-        Err(err) => {
-            eprintln!("Database query error: {:#?}", err);
-            return HttpResponse::InternalServerError().body("Internal Server Error");
-        }
-    };
+    let row = sqlx::query(query)
+        .bind(id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or(AppError::NotFound)?;
 
-    let product: DetailsProduct = match map_row_to_product(row).await {
-        Ok(product) => product,
-        Err(err) => {
-            eprintln!("Product mapping failed: {}", err);
-            return HttpResponse::InternalServerError().body("Internal Server Error");
-        }
-    };
+    if !row
+        .try_get::<Option<bool>, _>("is_active")?
+        .unwrap_or(false)
+    {
+        let name: String = row.try_get("name")?;
+        let mut context = Context::new();
+        context.insert("title", "Ecommerce - No Longer Available");
+        context.insert("name", &name);
+        return utils::render_template_with_status(
+            &tmpl,
+            "product_unavailable.html",
+            &context,
+            StatusCode::GONE,
+        );
+    }
+
+    let product: DetailsProduct = map_row_to_product(row, currency.store).await?;
 
     let mut context = Context::new();
     context.insert("title", &product.name);
+    context.insert("currency", &currency);
     context.insert("product", &product);
 
     utils::render_template(&tmpl, "product_details.html", &context)
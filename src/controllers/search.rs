@@ -0,0 +1,144 @@
+use crate::errors::AppError;
+use crate::utils;
+use crate::utils::currency::CurrencyContext;
+use crate::utils::money::Money;
+use crate::utils::pagination::{PageQuery, Pagination};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Row};
+use stripe::Currency;
+use tera::{Context, Tera};
+
+// Imports reject control characters and the query strips these from the stored
+// text, so they are safe markers to wrap matches with before the text is
+// HTML-escaped.
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_STOP: char = '\u{3}';
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchProduct {
+    description: String,
+    description_html: String,
+    id: i32,
+    image_url: String,
+    name: String,
+    name_html: String,
+    price: Money,
+}
+
+fn highlight(text: &str) -> String {
+    tera::escape_html(text)
+        .replace(HIGHLIGHT_START, "<mark>")
+        .replace(HIGHLIGHT_STOP, "</mark>")
+}
+
+fn strip_highlight(text: &str) -> String {
+    text.replace([HIGHLIGHT_START, HIGHLIGHT_STOP], "")
+}
+
+fn map_row_to_product(
+    row: &sqlx::postgres::PgRow,
+    store: Currency,
+) -> Result<SearchProduct, String> {
+    let description: String = row
+        .try_get("description")
+        .map_err(|_| "Error getting `description`")?;
+    let id: i32 = row.try_get("id").map_err(|_| "Error getting `id`")?;
+    let image_url: String = row
+        .try_get("image_url")
+        .map_err(|_| "Error getting `image_url`")?;
+    let name: String = row.try_get("name").map_err(|_| "Error getting `name`")?;
+    let price = row
+        .try_get("price_cents")
+        .map(|cents| Money::new(cents, store))
+        .map_err(|_| "Error getting `price_cents`")?;
+
+    Ok(SearchProduct {
+        description: strip_highlight(&description),
+        description_html: highlight(&description),
+        id,
+        image_url,
+        name: strip_highlight(&name),
+        name_html: highlight(&name),
+        price,
+    })
+}
+
+pub async fn handler(
+    pool: web::Data<Pool<Postgres>>,
+    req: HttpRequest,
+    tmpl: web::Data<Tera>,
+    search: web::Query<SearchQuery>,
+    query: web::Query<PageQuery>,
+    currency: CurrencyContext,
+) -> Result<HttpResponse, AppError> {
+    let term = search.q.as_deref().unwrap_or_default().trim().to_string();
+
+    let mut context = Context::new();
+    context.insert("title", "Ecommerce - Search");
+    context.insert("currency", &currency);
+    context.insert("q", &term);
+
+    if term.is_empty() {
+        context.insert("products", &Vec::<SearchProduct>::new());
+        return utils::render_template(&tmpl, "search.html", &context);
+    }
+
+    let query_total = "
+        SELECT COUNT(*)
+        FROM products
+        WHERE is_active AND search_vector @@ websearch_to_tsquery('english', $1);
+        ";
+    let total: i64 = sqlx::query_scalar(query_total)
+        .bind(&term)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let query_products = "
+        SELECT
+            id,
+            image_url,
+            price_cents,
+            ts_headline('english', translate(name, $5, ''), query, $2) AS name,
+            ts_headline('english', translate(description, $5, ''), query, $2) AS description
+        FROM products, websearch_to_tsquery('english', $1) query
+        WHERE is_active AND search_vector @@ query
+        ORDER BY ts_rank(search_vector, query) DESC, id
+        LIMIT $3 OFFSET $4;
+        ";
+    let headline_options = format!(
+        "StartSel={}, StopSel={}, HighlightAll=true",
+        HIGHLIGHT_START, HIGHLIGHT_STOP
+    );
+    let rows = sqlx::query(query_products)
+        .bind(&term)
+        .bind(&headline_options)
+        .bind(query.per_page())
+        .bind(query.offset())
+        .bind(String::from_iter([HIGHLIGHT_START, HIGHLIGHT_STOP]))
+        .fetch_all(pool.get_ref())
+        .await?;
+
+    let products: Vec<SearchProduct> = rows
+        .into_iter()
+        .filter_map(|row| map_row_to_product(&row, currency.store).ok())
+        .collect();
+
+    let base_url = match serde_urlencoded::to_string([("q", &term)]) {
+        Ok(encoded) => format!("/search?{}", encoded),
+        Err(_) => return Err(AppError::Validation("Invalid search query".to_string())),
+    };
+
+    context.insert("products", &products);
+    context.insert("pagination", &Pagination::new(&query, total, &base_url));
+
+    if utils::is_htmx_request(&req) {
+        return utils::render_template(&tmpl, "_products.html", &context);
+    }
+    utils::render_template(&tmpl, "search.html", &context)
+}
@@ -0,0 +1,129 @@
+use crate::utils::is_htmx_request;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::{web, HttpResponse, ResponseError};
+use std::fmt;
+use tera::{Context, Tera};
+
+#[derive(Debug)]
+pub enum AppError {
+    Database(sqlx::Error),
+    NotFound,
+    Payment(String),
+    Template(tera::Error),
+    Validation(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "Database error: {}", err),
+            AppError::NotFound => write!(f, "Not found"),
+            AppError::Payment(message) => write!(f, "Payment error: {}", message),
+            AppError::Template(err) => write!(f, "Template error: {:?}", err),
+            AppError::Validation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<tera::Error> for AppError {
+    fn from(err: tera::Error) -> Self {
+        AppError::Template(err)
+    }
+}
+
+impl From<stripe::StripeError> for AppError {
+    fn from(err: stripe::StripeError) -> Self {
+        AppError::Payment(err.to_string())
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Database(_) | AppError::Payment(_) | AppError::Template(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            eprintln!("[{}] {}", status.as_u16(), self);
+        }
+        match self {
+            AppError::Validation(message) => HttpResponse::build(status).body(message.clone()),
+            _ => HttpResponse::build(status).body(status.canonical_reason().unwrap_or_default()),
+        }
+    }
+}
+
+/// Replaces the plain-text body of error responses with the branded error pages.
+pub fn error_pages<B: 'static>() -> ErrorHandlers<B> {
+    ErrorHandlers::new()
+        .handler(StatusCode::BAD_REQUEST, |res| {
+            render_error_page(res, "400.html", "Ecommerce - Bad Request")
+        })
+        .handler(StatusCode::NOT_FOUND, |res| {
+            render_error_page(res, "404.html", "Ecommerce - Not Found")
+        })
+        .handler(StatusCode::INTERNAL_SERVER_ERROR, |res| {
+            render_error_page(res, "500.html", "Ecommerce - Error")
+        })
+}
+
+fn render_error_page<B>(
+    res: ServiceResponse<B>,
+    template_name: &str,
+    title: &str,
+) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    // htmx swaps fragments, so it gets the plain-text message instead of a page.
+    if is_htmx_request(res.request()) {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    let tmpl = match res.request().app_data::<web::Data<Tera>>() {
+        Some(tmpl) => tmpl.clone(),
+        None => return Ok(ErrorHandlerResponse::Response(res.map_into_left_body())),
+    };
+
+    let mut context = Context::new();
+    context.insert("title", title);
+    if let Some(AppError::Validation(message)) =
+        res.response().error().and_then(|err| err.as_error())
+    {
+        context.insert("message", message);
+    }
+
+    let rendered = match tmpl.render(template_name, &context) {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            eprintln!("[{}] {}", res.status().as_u16(), AppError::Template(err));
+            return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+        }
+    };
+
+    let (req, mut res) = res.into_parts();
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    let res = ServiceResponse::new(req, res.set_body(rendered))
+        .map_into_boxed_body()
+        .map_into_right_body();
+
+    Ok(ErrorHandlerResponse::Response(res))
+}
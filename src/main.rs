@@ -1,54 +1,140 @@
+mod cli;
+mod config;
 mod controllers;
+mod errors;
+mod services;
 mod utils;
 
 use actix_files::Files;
 use actix_web::{web, App, HttpResponse, HttpServer};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::Config;
 use controllers::{
-    add_to_cart, cart, home, not_found, payment, product_details, remove_from_cart, stripe_webhook,
+    add_to_cart, cart, categories, home, mock_payment, not_found, order_details, payment,
+    payment_complete, payment_details, product_details, remove_from_cart, search,
+    set_display_currency, webhook,
 };
 use dotenv::dotenv;
+use services::payments::PaymentProvider;
 use sqlx::{Pool, Postgres};
+use std::process;
+use std::time::Duration;
 use tera::Tera;
-// use utils::{create_database_pool, populate_database_with_mock_products, setup_database};
 use utils::create_database_pool;
+use utils::currency::{money_filter, ExchangeRates};
+use utils::public_url::url_function;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
-    let pool: Pool<Postgres> = create_database_pool()
+    match Cli::parse().command {
+        None | Some(Command::Serve) => serve().await,
+        Some(command) => process::exit(cli::run(command).await),
+    }
+}
+
+async fn serve() -> std::io::Result<()> {
+    let config = Config::load().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(cli::EXIT_CONFIG);
+    });
+
+    let pool: Pool<Postgres> = create_database_pool(&config.database)
         .await
-        .expect("Error creating database pool");
+        .unwrap_or_else(|err| {
+            eprintln!("Error connecting to the database: {}", err);
+            process::exit(cli::EXIT_FAILURE);
+        });
+
+    if config.database.auto_migrate {
+        // A failed migration leaves the database behind this build.
+        if let Err(err) = utils::MIGRATOR.run(&pool).await {
+            eprintln!("Error running database migrations: {}", err);
+            process::exit(cli::EXIT_PENDING_MIGRATIONS);
+        }
+    } else {
+        let pending = utils::pending_migrations(&pool)
+            .await
+            .unwrap_or_else(|err| {
+                eprintln!("Error checking database migrations: {}", err);
+                process::exit(cli::EXIT_FAILURE);
+            });
+        if cli::report_pending(&pending) {
+            eprintln!("Run `ecommerce migrate` or set `AUTO_MIGRATE=true` to apply them.");
+            process::exit(cli::EXIT_PENDING_MIGRATIONS);
+        }
+    }
 
-    // setup_database(pool.clone())
-    // .await
-    // .expect("Error setting up the database");
-    // populate_database_with_mock_products(pool.clone())
-    // .await
-    // .expect("Error populating the database with products");
+    // Stock held by abandoned checkouts goes back on sale once it expires.
+    let sweep_pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(err) = services::inventory::release_expired(&sweep_pool).await {
+                eprintln!("[inventory] Error releasing expired reservations: {}", err);
+            }
+        }
+    });
 
     let pool_data = web::Data::new(pool);
-    let tera = Tera::new("src/html/*").expect("Error initializing Tera");
+    let store_currency = web::Data::new(config.store_currency);
+    let exchange_rates = web::Data::new(ExchangeRates::default());
+    let cart_cookie_key = web::Data::new(config.cart_cookie_key.clone());
+    let providers = services::payments::from_config(&config.payment);
+    let payment_provider: web::Data<dyn PaymentProvider> = web::Data::from(providers.provider);
+    let mock_payment_provider = providers.mock.map(web::Data::from);
+    let mut tera = Tera::new(&config.template_glob).unwrap_or_else(|err| {
+        eprintln!(
+            "Error loading templates from {}: {}",
+            config.template_glob, err
+        );
+        process::exit(cli::EXIT_CONFIG);
+    });
+    tera.register_filter("money", money_filter);
+    tera.register_function("url", url_function(config.public_url.clone()));
+    let bind_address = config.bind_address.clone();
+    let config = web::Data::new(config);
 
     HttpServer::new(move || {
-        App::new()
+        let mut app = App::new()
+            .wrap(errors::error_pages())
             .app_data(pool_data.clone())
+            .app_data(config.clone())
             .app_data(web::Data::new(tera.clone()))
+            .app_data(store_currency.clone())
+            .app_data(exchange_rates.clone())
+            .app_data(cart_cookie_key.clone())
+            .app_data(payment_provider.clone())
             .route("/", web::get().to(home::handler))
             .route(
                 "/status",
                 web::get().to(|| async { HttpResponse::Ok().body("ok") }),
             )
             .route("/product/{id}", web::get().to(product_details::handler))
+            .route("/categories", web::get().to(categories::index))
+            .route("/category/{slug}", web::get().to(categories::handler))
+            .route("/search", web::get().to(search::handler))
             .route("/cart", web::get().to(cart::handler))
             .route("/add_to_cart/{id}", web::post().to(add_to_cart))
             .route("/remove_from_cart/{id}", web::post().to(remove_from_cart))
+            .route("/currency", web::post().to(set_display_currency))
             .route("/payment", web::get().to(payment))
-            .route("/stripe-webhook", web::get().to(stripe_webhook))
+            .route("/payment/complete", web::get().to(payment_complete))
+            .route("/payment/details", web::post().to(payment_details))
+            .route("/payment/mock", web::post().to(mock_payment))
+            .route("/order/{id}", web::get().to(order_details::handler))
+            .route("/stripe-webhook", web::post().to(webhook::handler))
             .service(Files::new("/public", "src/public").show_files_listing())
-            .default_service(web::route().to(not_found))
+            .default_service(web::route().to(not_found));
+        if let Some(mock_payment_provider) = &mock_payment_provider {
+            app = app.app_data(mock_payment_provider.clone());
+        }
+        app
     })
-    .bind("127.0.0.1:8080")?
+    .bind(bind_address)?
     .run()
     .await
 }
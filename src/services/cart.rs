@@ -0,0 +1,417 @@
+use super::inventory;
+use crate::errors::AppError;
+use crate::utils::money::Money;
+use actix_web::cookie::{time::Duration, Cookie, CookieBuilder, SameSite};
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures::future::LocalBoxFuture;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{Pool, Postgres, Row};
+use stripe::Currency;
+
+pub const CART_COOKIE: &str = "cart";
+
+/// Version tag of the `cart` cookie, which holds `v1.<cart id>.<hex HMAC-SHA256>`.
+/// Older formats are migrated by `CartSession` when they are seen.
+const CART_COOKIE_VERSION: &str = "v1";
+
+/// Largest quantity a single cart line may hold.
+pub const MAX_QUANTITY: i32 = 100;
+
+/// Secret the `cart` cookie is signed with, from `CART_COOKIE_SECRET`.
+#[derive(Clone)]
+pub struct CartCookieKey(Vec<u8>);
+
+impl CartCookieKey {
+    pub fn new(secret: String) -> Result<Self, String> {
+        if secret.len() < 32 {
+            return Err("must be at least 32 bytes long".to_string());
+        }
+        Ok(CartCookieKey(secret.into_bytes()))
+    }
+
+    fn mac(&self, cart_id: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(CART_COOKIE_VERSION.as_bytes());
+        mac.update(b".");
+        mac.update(cart_id.as_bytes());
+        mac
+    }
+
+    fn sign(&self, cart_id: &str) -> String {
+        let signature = hex::encode(self.mac(cart_id).finalize().into_bytes());
+        format!("{}.{}.{}", CART_COOKIE_VERSION, cart_id, signature)
+    }
+
+    fn decode(&self, value: &str) -> Option<CartCookie> {
+        match value.split_once('.') {
+            Some((CART_COOKIE_VERSION, signed)) => {
+                let (cart_id, signature) = signed.split_once('.')?;
+                let signature = hex::decode(signature).ok()?;
+                self.mac(cart_id).verify_slice(&signature).ok()?;
+                is_cart_id(cart_id).then(|| CartCookie::Session(cart_id.to_string()))
+            }
+            Some(_) => None,
+            None => parse_legacy_cookie(value).map(CartCookie::Legacy),
+        }
+    }
+}
+
+enum CartCookie {
+    /// Pre-versioned cookie holding the cart itself as `id:qty,id:qty`.
+    Legacy(Vec<(i32, i32)>),
+    Session(String),
+}
+
+/// Keeps the well-formed entries of a legacy cookie, clamping quantities.
+fn parse_legacy_cookie(value: &str) -> Option<Vec<(i32, i32)>> {
+    let items: Vec<(i32, i32)> = value
+        .split(',')
+        .filter_map(|item| {
+            let (id, quantity) = item.split_once(':')?;
+            let quantity = quantity.parse::<i32>().ok().filter(|q| *q > 0)?;
+            Some((id.parse::<i32>().ok()?, quantity.min(MAX_QUANTITY)))
+        })
+        .collect();
+    (!items.is_empty()).then_some(items)
+}
+
+#[derive(Serialize)]
+pub struct CartLine {
+    pub exceeds_stock: bool,
+    pub id: i32,
+    pub is_active: bool,
+    pub name: String,
+    pub price: Money,
+    pub quantity: i32,
+    /// Stock left for this cart, net of other checkouts' holds.
+    pub stock_quantity: i32,
+    pub total_price_item: Money,
+}
+
+fn is_cart_id(value: &str) -> bool {
+    value.len() == 36
+        && value.chars().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// The visitor's cart, resolved from the signed `cart` cookie.
+///
+/// Legacy cookies are moved into a new server-side cart and invalid ones are
+/// dropped, so `response()` re-issues or clears the cookie when needed.
+pub struct CartSession {
+    pub id: Option<String>,
+    key: web::Data<CartCookieKey>,
+    reissue: bool,
+}
+
+impl CartSession {
+    pub fn cookie(&self, cart_id: &str) -> Cookie<'static> {
+        CookieBuilder::new(CART_COOKIE, self.key.sign(cart_id))
+            .path("/")
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .max_age(Duration::weeks(4))
+            .finish()
+    }
+
+    /// `200 OK` builder carrying the re-issued or cleared cookie, if any.
+    pub fn response(&self) -> HttpResponseBuilder {
+        let mut response = HttpResponse::Ok();
+        if self.reissue {
+            match &self.id {
+                Some(cart_id) => response.cookie(self.cookie(cart_id)),
+                None => response.cookie(
+                    CookieBuilder::new(CART_COOKIE, "")
+                        .path("/")
+                        .max_age(Duration::ZERO)
+                        .finish(),
+                ),
+            };
+        }
+        response
+    }
+
+    async fn resolve(
+        pool: &Pool<Postgres>,
+        key: web::Data<CartCookieKey>,
+        value: Option<String>,
+    ) -> Result<Self, sqlx::Error> {
+        let (id, reissue) = match value.as_deref().map(|value| key.decode(value)) {
+            None => (None, false),
+            Some(None) => (None, true),
+            Some(Some(CartCookie::Session(cart_id))) => (Some(cart_id), false),
+            Some(Some(CartCookie::Legacy(items))) => {
+                (Some(migrate_legacy_cart(pool, &items).await?), true)
+            }
+        };
+        Ok(CartSession { id, key, reissue })
+    }
+}
+
+impl FromRequest for CartSession {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = web::Data::<Pool<Postgres>>::extract(req);
+        let key = web::Data::<CartCookieKey>::extract(req);
+        let value = req
+            .cookie(CART_COOKIE)
+            .map(|cookie| cookie.value().to_string())
+            .filter(|value| !value.is_empty());
+
+        Box::pin(async move {
+            let pool = pool.await?;
+            let key = key.await?;
+            let session = Self::resolve(&pool, key, value)
+                .await
+                .map_err(AppError::from)?;
+            Ok(session)
+        })
+    }
+}
+
+/// Copies the items of a legacy cookie into a new cart, skipping unknown products.
+async fn migrate_legacy_cart(
+    pool: &Pool<Postgres>,
+    items: &[(i32, i32)],
+) -> Result<String, sqlx::Error> {
+    let cart_id = ensure_cart(pool, None).await?;
+    let (product_ids, quantities): (Vec<i32>, Vec<i32>) = items.iter().copied().unzip();
+    sqlx::query(
+        "INSERT INTO cart_items (cart_id, product_id, quantity)
+        SELECT $1::uuid, p.id, items.quantity
+        FROM UNNEST($2::INT[], $3::INT[]) AS items (product_id, quantity)
+        JOIN products p ON p.id = items.product_id
+        ON CONFLICT (cart_id, product_id) DO NOTHING",
+    )
+    .bind(&cart_id)
+    .bind(&product_ids)
+    .bind(&quantities)
+    .execute(pool)
+    .await?;
+    Ok(cart_id)
+}
+
+/// Returns the existing cart for `cart_id`, or creates a new one when the id
+/// is missing or no longer known.
+pub async fn ensure_cart(
+    pool: &Pool<Postgres>,
+    cart_id: Option<String>,
+) -> Result<String, sqlx::Error> {
+    if let Some(cart_id) = cart_id {
+        let existing: Option<String> = sqlx::query_scalar(
+            "UPDATE carts SET updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1::uuid
+            RETURNING id::text",
+        )
+        .bind(&cart_id)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(existing) = existing {
+            return Ok(existing);
+        }
+    }
+    sqlx::query_scalar("INSERT INTO carts DEFAULT VALUES RETURNING id::text")
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn set_quantity(
+    pool: &Pool<Postgres>,
+    cart_id: &str,
+    product_id: i32,
+    quantity: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO cart_items (cart_id, product_id, quantity)
+        VALUES ($1::uuid, $2, $3)
+        ON CONFLICT (cart_id, product_id)
+        DO UPDATE SET quantity = EXCLUDED.quantity, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(cart_id)
+    .bind(product_id)
+    .bind(quantity)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_item(
+    pool: &Pool<Postgres>,
+    cart_id: &str,
+    product_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM cart_items WHERE cart_id = $1::uuid AND product_id = $2")
+        .bind(cart_id)
+        .bind(product_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Drops lines that can no longer be bought and lowers quantities to the
+/// stock left; returns how many lines changed.
+pub async fn fit_to_stock(pool: &Pool<Postgres>, cart_id: &str) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let removed = sqlx::query(
+        "DELETE FROM cart_items ci
+        WHERE ci.cart_id = $1::uuid
+        AND NOT EXISTS (
+            SELECT 1 FROM products p
+            WHERE p.id = ci.product_id AND p.is_active AND p.stock_quantity > 0
+        )",
+    )
+    .bind(cart_id)
+    .execute(&mut *tx)
+    .await?;
+    let lowered = sqlx::query(
+        "UPDATE cart_items ci
+        SET quantity = p.stock_quantity, updated_at = CURRENT_TIMESTAMP
+        FROM products p
+        WHERE ci.cart_id = $1::uuid
+        AND p.id = ci.product_id
+        AND ci.quantity > p.stock_quantity",
+    )
+    .bind(cart_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(removed.rows_affected() + lowered.rows_affected())
+}
+
+pub async fn clear(pool: &Pool<Postgres>, cart_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM cart_items WHERE cart_id = $1::uuid")
+        .bind(cart_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn lines(
+    pool: &Pool<Postgres>,
+    cart_id: &str,
+    store: Currency,
+) -> Result<Vec<CartLine>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT p.id, p.name, p.price_cents, p.is_active, {} AS stock_quantity, ci.quantity
+        FROM cart_items ci
+        JOIN products p ON p.id = ci.product_id
+        WHERE ci.cart_id = $1::uuid
+        ORDER BY ci.created_at, p.id",
+        inventory::AVAILABLE_STOCK
+    ))
+    .bind(cart_id)
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let price = Money::new(row.try_get("price_cents")?, store);
+            let quantity: i32 = row.try_get("quantity")?;
+            let stock_quantity: i32 = row.try_get("stock_quantity")?;
+            Ok(CartLine {
+                exceeds_stock: quantity > stock_quantity,
+                id: row.try_get("id")?,
+                is_active: row
+                    .try_get::<Option<bool>, _>("is_active")?
+                    .unwrap_or(false),
+                name: row.try_get("name")?,
+                price,
+                quantity,
+                stock_quantity,
+                total_price_item: price * quantity,
+            })
+        })
+        .collect()
+}
+
+/// Sum of the lines that can still be bought.
+pub fn total(lines: &[CartLine], store: Currency) -> Money {
+    lines
+        .iter()
+        .filter(|line| line.is_active)
+        .fold(Money::zero(store), |total, line| {
+            total + line.total_price_item
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CART_ID: &str = "6f1c2a3b-4d5e-4f60-8a7b-9c0d1e2f3a4b";
+
+    fn cookie_key(secret: &str) -> CartCookieKey {
+        CartCookieKey::new(secret.repeat(32)).unwrap()
+    }
+
+    fn session_id(cookie: Option<CartCookie>) -> Option<String> {
+        match cookie {
+            Some(CartCookie::Session(cart_id)) => Some(cart_id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn short_secrets_are_rejected() {
+        assert!(CartCookieKey::new("too short".to_string()).is_err());
+        assert!(CartCookieKey::new("x".repeat(32)).is_ok());
+    }
+
+    #[test]
+    fn signed_cookies_decode_to_their_cart() {
+        let key = cookie_key("k");
+        let value = key.sign(CART_ID);
+
+        assert!(value.starts_with("v1.6f1c2a3b-"));
+        assert_eq!(session_id(key.decode(&value)), Some(CART_ID.to_string()));
+    }
+
+    #[test]
+    fn tampered_cookies_are_rejected() {
+        let key = cookie_key("k");
+        let value = key.sign(CART_ID);
+
+        let mut flipped = value.clone();
+        let last = if flipped.ends_with('0') { "1" } else { "0" };
+        flipped.replace_range(flipped.len() - 1.., last);
+        assert!(key.decode(&flipped).is_none());
+
+        let other_cart = value.replace("6f1c2a3b", "00000000");
+        assert!(key.decode(&other_cart).is_none());
+
+        assert!(key.decode(&format!("v1.{}.not-hex", CART_ID)).is_none());
+        assert!(key.decode(&format!("v1.{}", CART_ID)).is_none());
+        assert!(key.decode(&value).is_some());
+        assert!(cookie_key("other").decode(&value).is_none());
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let key = cookie_key("k");
+        let value = key.sign(CART_ID);
+
+        assert!(key.decode(&value.replacen("v1.", "v2.", 1)).is_none());
+        assert!(key.decode(&value.replacen("v1.", "", 1)).is_none());
+    }
+
+    #[test]
+    fn legacy_cookies_are_migrated() {
+        let key = cookie_key("k");
+
+        match key.decode("3:2,5:500,bad,7:0,x:1,9:-1") {
+            Some(CartCookie::Legacy(items)) => assert_eq!(items, vec![(3, 2), (5, MAX_QUANTITY)]),
+            _ => panic!("expected a legacy cart"),
+        }
+        assert!(key.decode("bad,7:0").is_none());
+        assert!(key.decode("").is_none());
+    }
+}
@@ -0,0 +1,633 @@
+use crate::utils::money::Money;
+use csv::{ReaderBuilder, StringRecord, Writer};
+use sqlx::{Connection, PgConnection, Pool, Postgres, Row};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use stripe::Currency;
+
+/// Columns of catalog CSV files, in the order `export` writes them. Files may
+/// list them in any order and leave out the optional ones.
+const COLUMNS: [&str; 8] = [
+    "id",
+    "name",
+    "description",
+    "price",
+    "stock_quantity",
+    "category",
+    "image_url",
+    "is_active",
+];
+
+const REQUIRED_COLUMNS: [&str; 4] = ["id", "name", "price", "stock_quantity"];
+
+/// A product read from a catalog file. Optional columns the file leaves out
+/// are `None`: they keep their current value when an existing product is
+/// updated, and are stored empty for a new one, as the pages expect text.
+pub struct ProductRow {
+    pub category: Option<String>,
+    pub description: Option<String>,
+    pub id: i32,
+    pub image_url: Option<String>,
+    pub is_active: Option<bool>,
+    pub name: String,
+    pub price: Money,
+    pub stock_quantity: i32,
+}
+
+/// Why a line of a catalog file was rejected. `column` is `None` for problems
+/// with the line as a whole.
+pub struct RowError {
+    pub column: Option<String>,
+    pub line: u64,
+    pub reason: String,
+}
+
+/// The valid products of a catalog file, by line, and the problems of the rest.
+#[derive(Default)]
+pub struct ParsedCatalog {
+    pub errors: Vec<RowError>,
+    pub products: Vec<(u64, ProductRow)>,
+}
+
+/// Reads catalog CSV, validating every line rather than stopping at the first
+/// bad one. Only unreadable input fails outright.
+pub fn read_products(
+    reader: impl Read,
+    store_currency: Currency,
+) -> Result<ParsedCatalog, csv::Error> {
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(true)
+        .from_reader(reader);
+    let mut catalog = ParsedCatalog::default();
+
+    let headers = rdr.headers()?.clone();
+    let columns = match read_header(&headers) {
+        Ok(columns) => columns,
+        Err(errors) => {
+            catalog.errors = errors;
+            return Ok(catalog);
+        }
+    };
+
+    let mut seen_ids = HashMap::new();
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                catalog.errors.push(RowError {
+                    column: None,
+                    line: err.position().map(|position| position.line()).unwrap_or(0),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record
+            .position()
+            .map(|position| position.line())
+            .unwrap_or(0);
+        if record.len() != headers.len() {
+            catalog.errors.push(RowError {
+                column: None,
+                line,
+                reason: format!("Expected {} fields, found {}", headers.len(), record.len()),
+            });
+            continue;
+        }
+
+        let mut row = RowParser {
+            columns: &columns,
+            errors: Vec::new(),
+            line,
+            record: &record,
+        };
+        let product = row.product(store_currency);
+        if let Some(product) = &product {
+            if let Some(first_line) = seen_ids.insert(product.id, line) {
+                row.error("id", format!("Duplicate of line {}", first_line));
+            }
+        }
+        match (product, row.errors.is_empty()) {
+            (Some(product), true) => catalog.products.push((line, product)),
+            _ => catalog.errors.append(&mut row.errors),
+        }
+    }
+    Ok(catalog)
+}
+
+/// Maps column names to field positions, reporting unknown, repeated and
+/// missing columns.
+fn read_header(headers: &StringRecord) -> Result<HashMap<&'static str, usize>, Vec<RowError>> {
+    let mut columns = HashMap::new();
+    let mut errors = Vec::new();
+    let mut header_error = |column: &str, reason: &str| {
+        errors.push(RowError {
+            column: Some(column.to_string()),
+            line: 1,
+            reason: reason.to_string(),
+        })
+    };
+    for (position, header) in headers.iter().enumerate() {
+        let name = header.trim().to_lowercase();
+        match COLUMNS.iter().find(|column| **column == name) {
+            Some(column) if columns.contains_key(column) => {
+                header_error(header, "Column appears more than once")
+            }
+            Some(column) => {
+                columns.insert(*column, position);
+            }
+            None => header_error(header, "Unknown column"),
+        }
+    }
+    for column in REQUIRED_COLUMNS {
+        if !columns.contains_key(column) {
+            header_error(column, "Missing required column");
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(columns),
+        false => Err(errors),
+    }
+}
+
+struct RowParser<'a> {
+    columns: &'a HashMap<&'static str, usize>,
+    errors: Vec<RowError>,
+    line: u64,
+    record: &'a StringRecord,
+}
+
+impl RowParser<'_> {
+    fn error(&mut self, column: &str, reason: String) {
+        self.errors.push(RowError {
+            column: Some(column.to_string()),
+            line: self.line,
+            reason,
+        });
+    }
+
+    /// The trimmed field, `None` when the file has no such column.
+    fn field(&self, column: &str) -> Option<&str> {
+        self.columns
+            .get(column)
+            .and_then(|position| self.record.get(*position))
+            .map(str::trim)
+    }
+
+    fn parse<T>(&mut self, column: &str, parse: impl Fn(&str) -> Result<T, String>) -> Option<T> {
+        let value = self.field(column)?;
+        parse(value)
+            .map_err(|reason| self.error(column, reason))
+            .ok()
+    }
+
+    fn text(&mut self, column: &str, max_chars: usize, is_required: bool) -> Option<String> {
+        self.parse(column, |value| {
+            if is_required && value.is_empty() {
+                return Err("Must not be empty".to_string());
+            }
+            if value.chars().count() > max_chars {
+                return Err(format!("Longer than {} characters", max_chars));
+            }
+            // Search results use control characters to mark matches.
+            if value.chars().any(char::is_control) {
+                return Err("Must not contain control characters".to_string());
+            }
+            Ok(value.to_string())
+        })
+    }
+
+    /// Parses every column, so all of the line's problems are reported.
+    fn product(&mut self, store_currency: Currency) -> Option<ProductRow> {
+        let id = self.parse("id", |value| match value.parse::<i32>() {
+            Ok(id) if id > 0 => Ok(id),
+            _ => Err(format!("`{}` is not a positive integer", value)),
+        });
+        let name = self.text("name", 50, true);
+        let description = self.text("description", 255, false);
+        let price = self.parse("price", |value| Money::parse(value, store_currency));
+        let stock_quantity = self.parse("stock_quantity", |value| match value.parse::<i32>() {
+            Ok(stock_quantity) if stock_quantity >= 0 => Ok(stock_quantity),
+            _ => Err(format!("`{}` is not a non-negative integer", value)),
+        });
+        let category = self.text("category", 50, false);
+        let image_url = self.parse("image_url", |value| {
+            let is_url = value.is_empty()
+                || value.starts_with("https://")
+                || value.starts_with("http://")
+                || value.starts_with('/');
+            match (is_url, value.chars().count() <= 255) {
+                (false, _) => Err(format!("`{}` is not an http(s) URL or a path", value)),
+                (_, false) => Err("Longer than 255 characters".to_string()),
+                _ => Ok(value.to_string()),
+            }
+        });
+        let is_active = self.parse("is_active", |value| match value.to_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(true),
+            "false" | "0" | "no" => Ok(false),
+            _ => Err(format!("`{}` is not true or false", value)),
+        });
+
+        Some(ProductRow {
+            category,
+            description,
+            id: id?,
+            image_url,
+            is_active,
+            name: name?,
+            price: price?,
+            stock_quantity: stock_quantity?,
+        })
+    }
+}
+
+/// Keeps `products.id` handing out ids above the ones written explicitly.
+async fn sync_id_sequence(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "SELECT setval(pg_get_serial_sequence('products', 'id'), COALESCE(MAX(id), 0) + 1, false)
+        FROM products",
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Inserts the products that don't exist yet, leaving existing ones untouched.
+/// Returns how many were inserted.
+pub async fn seed(pool: &Pool<Postgres>, products: &[ProductRow]) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut inserted = 0;
+    for product in products {
+        let result = sqlx::query(
+            "INSERT INTO products
+            (id, name, description, price_cents, stock_quantity, category, image_url, is_active)
+            VALUES ($1, $2, COALESCE($3, ''), $4, $5, COALESCE($6, ''), COALESCE($7, ''), COALESCE($8, TRUE))
+            ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(product.id)
+        .bind(&product.name)
+        .bind(&product.description)
+        .bind(product.price.minor_units())
+        .bind(product.stock_quantity)
+        .bind(&product.category)
+        .bind(&product.image_url)
+        .bind(product.is_active)
+        .execute(&mut *tx)
+        .await?;
+        inserted += result.rows_affected();
+    }
+    sync_id_sequence(&mut tx).await?;
+    tx.commit().await?;
+    Ok(inserted)
+}
+
+#[derive(Clone, Copy)]
+pub struct ImportOptions {
+    /// Write nothing at all when any line is rejected, instead of skipping it.
+    pub atomic: bool,
+    /// Validate and report without writing anything.
+    pub dry_run: bool,
+}
+
+/// What an import did, or would have done when nothing was written.
+pub struct ImportReport {
+    pub errors: Vec<RowError>,
+    pub inserted: u64,
+    pub is_written: bool,
+    pub updated: u64,
+}
+
+/// Creates or updates a product; returns whether it was created.
+async fn upsert(conn: &mut PgConnection, product: &ProductRow) -> Result<bool, sqlx::Error> {
+    // `xmax = 0` only holds for freshly inserted rows.
+    sqlx::query_scalar(
+        "INSERT INTO products
+        (id, name, description, price_cents, stock_quantity, category, image_url, is_active)
+        VALUES ($1, $2, COALESCE($3, ''), $4, $5, COALESCE($6, ''), COALESCE($7, ''), COALESCE($8, TRUE))
+        ON CONFLICT (id) DO UPDATE
+        SET name = EXCLUDED.name,
+            description = COALESCE($3, products.description),
+            price_cents = EXCLUDED.price_cents,
+            stock_quantity = EXCLUDED.stock_quantity,
+            category = COALESCE($6, products.category),
+            image_url = COALESCE($7, products.image_url),
+            is_active = COALESCE($8, products.is_active),
+            updated_at = CURRENT_TIMESTAMP
+        RETURNING xmax = 0",
+    )
+    .bind(product.id)
+    .bind(&product.name)
+    .bind(&product.description)
+    .bind(product.price.minor_units())
+    .bind(product.stock_quantity)
+    .bind(&product.category)
+    .bind(&product.image_url)
+    .bind(product.is_active)
+    .fetch_one(conn)
+    .await
+}
+
+/// Creates or updates every valid product of the catalog, each in its own
+/// savepoint so a line the database rejects is reported and skipped like an
+/// invalid one.
+pub async fn import(
+    pool: &Pool<Postgres>,
+    catalog: ParsedCatalog,
+    options: ImportOptions,
+) -> Result<ImportReport, sqlx::Error> {
+    let mut report = ImportReport {
+        errors: catalog.errors,
+        inserted: 0,
+        is_written: false,
+        updated: 0,
+    };
+    if options.atomic && !report.errors.is_empty() {
+        return Ok(report);
+    }
+
+    let mut tx = pool.begin().await?;
+    for (line, product) in &catalog.products {
+        let mut savepoint = tx.begin().await?;
+        match upsert(&mut savepoint, product).await {
+            Ok(is_insert) => {
+                savepoint.commit().await?;
+                match is_insert {
+                    true => report.inserted += 1,
+                    false => report.updated += 1,
+                }
+            }
+            Err(sqlx::Error::Database(err)) => {
+                savepoint.rollback().await?;
+                report.errors.push(RowError {
+                    column: None,
+                    line: *line,
+                    reason: err.message().to_string(),
+                });
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let is_rejected = options.atomic && !report.errors.is_empty();
+    report.is_written = !options.dry_run && !is_rejected;
+    // `setval` ignores rollbacks, so the sequence only moves for a written import.
+    match report.is_written {
+        true => {
+            sync_id_sequence(&mut tx).await?;
+            tx.commit().await?
+        }
+        false => tx.rollback().await?,
+    }
+    report.errors.sort_by_key(|error| error.line);
+    Ok(report)
+}
+
+/// Writes the report's rejected lines as `line,column,reason` CSV.
+pub fn write_errors(errors: &[RowError], writer: impl Write) -> Result<(), csv::Error> {
+    let mut wtr = Writer::from_writer(writer);
+    wtr.write_record(["line", "column", "reason"])?;
+    for error in errors {
+        wtr.write_record([
+            error.line.to_string().as_str(),
+            error.column.as_deref().unwrap_or(""),
+            error.reason.as_str(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes the whole catalog as CSV, in the format `import` reads. Returns how
+/// many products were written.
+pub async fn export(
+    pool: &Pool<Postgres>,
+    writer: impl Write,
+    store_currency: Currency,
+) -> Result<u64, Box<dyn Error>> {
+    let rows = sqlx::query(
+        "SELECT id, name, description, price_cents, stock_quantity, category, image_url, is_active
+        FROM products
+        ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut wtr = Writer::from_writer(writer);
+    wtr.write_record(COLUMNS)?;
+    for row in &rows {
+        let price = Money::new(row.try_get("price_cents")?, store_currency);
+        wtr.write_record([
+            row.try_get::<i32, _>("id")?.to_string(),
+            row.try_get("name")?,
+            row.try_get::<Option<String>, _>("description")?
+                .unwrap_or_default(),
+            price.to_string(),
+            row.try_get::<i32, _>("stock_quantity")?.to_string(),
+            row.try_get::<Option<String>, _>("category")?
+                .unwrap_or_default(),
+            row.try_get::<Option<String>, _>("image_url")?
+                .unwrap_or_default(),
+            row.try_get::<Option<bool>, _>("is_active")?
+                .unwrap_or(true)
+                .to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(rows.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(csv: &str) -> ParsedCatalog {
+        read_products(csv.as_bytes(), Currency::EUR).expect("readable CSV")
+    }
+
+    /// `(line, column)` of every error, in the order they were found.
+    fn error_positions(errors: &[RowError]) -> Vec<(u64, Option<&str>)> {
+        errors
+            .iter()
+            .map(|error| (error.line, error.column.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn columns_are_matched_by_name_in_any_order() {
+        let catalog = read("stock_quantity, Name ,ID,price\n5,Mug,7,3.50\n");
+
+        assert!(catalog.errors.is_empty());
+        let (line, product) = &catalog.products[0];
+        assert_eq!(*line, 2);
+        assert_eq!(product.id, 7);
+        assert_eq!(product.name, "Mug");
+        assert_eq!(product.price, Money::new(350, Currency::EUR));
+        assert_eq!(product.stock_quantity, 5);
+        assert!(product.category.is_none());
+        assert!(product.description.is_none());
+        assert!(product.image_url.is_none());
+        assert!(product.is_active.is_none());
+    }
+
+    #[test]
+    fn read_header_maps_positions() {
+        let headers = StringRecord::from(vec!["price", "id", "stock_quantity", "name", "category"]);
+        let columns = read_header(&headers).ok().expect("valid header");
+
+        assert_eq!(columns["price"], 0);
+        assert_eq!(columns["id"], 1);
+        assert_eq!(columns["category"], 4);
+        assert!(!columns.contains_key("description"));
+    }
+
+    #[test]
+    fn read_header_reports_unknown_duplicate_and_missing_columns() {
+        let headers = StringRecord::from(vec!["id", "name", "NAME", "colour"]);
+        let errors = read_header(&headers).expect_err("invalid header");
+
+        assert_eq!(
+            error_positions(&errors),
+            vec![
+                (1, Some("NAME")),
+                (1, Some("colour")),
+                (1, Some("price")),
+                (1, Some("stock_quantity")),
+            ]
+        );
+        assert_eq!(errors[0].reason, "Column appears more than once");
+        assert_eq!(errors[1].reason, "Unknown column");
+        assert_eq!(errors[2].reason, "Missing required column");
+    }
+
+    #[test]
+    fn a_bad_header_rejects_the_whole_file() {
+        let catalog = read("id,name,price\n1,Mug,3.50\n");
+
+        assert!(catalog.products.is_empty());
+        assert_eq!(
+            error_positions(&catalog.errors),
+            vec![(1, Some("stock_quantity"))]
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_line_and_column() {
+        let catalog = read(
+            "id,name,price,stock_quantity,image_url,is_active\n\
+             1,Mug,3.50,5,/images/mug.png,true\n\
+             0,,abc,-1,ftp://example.com/mug.png,maybe\n\
+             1,Cup,1.00,1,,false\n\
+             2,Plate\n\
+             3,Bowl,2.00,4,https://example.com/bowl.png,FALSE\n",
+        );
+
+        let lines: Vec<u64> = catalog.products.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![2, 6]);
+        assert_eq!(catalog.products[1].1.is_active, Some(false));
+
+        assert_eq!(
+            error_positions(&catalog.errors),
+            vec![
+                (3, Some("id")),
+                (3, Some("name")),
+                (3, Some("price")),
+                (3, Some("stock_quantity")),
+                (3, Some("image_url")),
+                (3, Some("is_active")),
+                (4, Some("id")),
+                (5, None),
+            ]
+        );
+        assert_eq!(catalog.errors[6].reason, "Duplicate of line 2");
+        assert_eq!(catalog.errors[7].reason, "Expected 6 fields, found 2");
+    }
+
+    #[test]
+    fn text_with_control_characters_is_rejected() {
+        let catalog = read(
+            "id,name,price,stock_quantity,description,category\n\
+             1,Mug\u{2},3.50,5,A mug,Kitchen\n\
+             2,Cup,1.00,1,A\u{3} cup,Kit\tchen\n",
+        );
+
+        assert!(catalog.products.is_empty());
+        assert_eq!(
+            error_positions(&catalog.errors),
+            vec![
+                (2, Some("name")),
+                (3, Some("description")),
+                (3, Some("category")),
+            ]
+        );
+        assert_eq!(
+            catalog.errors[0].reason,
+            "Must not contain control characters"
+        );
+    }
+
+    /// Imports a product from a file with only the required columns, then
+    /// renders its page. Needs a database in `TEST_DATABASE_URL`, which is
+    /// migrated and gets the product removed again; skipped when unset.
+    #[actix_web::test]
+    async fn products_imported_without_optional_columns_render() {
+        use crate::controllers::product_details;
+        use crate::utils::currency::{money_filter, ExchangeRates, StoreCurrency};
+        use crate::utils::public_url::{url_function, PublicUrl};
+        use actix_web::{test, web, App};
+        use tera::Tera;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping");
+            return;
+        };
+        let pool = Pool::<Postgres>::connect(&url)
+            .await
+            .expect("test database");
+        crate::utils::MIGRATOR.run(&pool).await.expect("migrations");
+
+        let id: i32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) + 1 FROM products")
+            .fetch_one(&pool)
+            .await
+            .expect("next id");
+        let catalog = read(&format!(
+            "id,name,price,stock_quantity\n{},Header Only Mug,3.50,5\n",
+            id
+        ));
+        let options = ImportOptions {
+            atomic: true,
+            dry_run: false,
+        };
+        let report = import(&pool, catalog, options).await.expect("import");
+        assert_eq!(report.inserted, 1);
+
+        let mut tera = Tera::new("src/html/**/*").expect("templates");
+        tera.register_filter("money", money_filter);
+        let public_url = PublicUrl::parse("http://localhost:8080").expect("public URL");
+        tera.register_function("url", url_function(public_url));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(StoreCurrency(Currency::EUR)))
+                .app_data(web::Data::new(ExchangeRates::default()))
+                .route("/product/{id}", web::get().to(product_details::handler)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri(&format!("/product/{}", id))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status();
+        let body = test::read_body(response).await;
+
+        sqlx::query("DELETE FROM products WHERE id = $1")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .expect("cleanup");
+        assert_eq!(status, 200);
+        assert!(String::from_utf8_lossy(&body).contains("Header Only Mug"));
+    }
+}
@@ -0,0 +1,199 @@
+use sqlx::{PgConnection, Pool, Postgres, Row};
+use std::collections::HashMap;
+
+/// How long stock stays held for a checkout that has not been paid yet.
+pub const RESERVATION_TTL_MINUTES: i32 = 30;
+
+/// SQL for the stock of product `p` that other carts' checkouts don't hold,
+/// with `$1` the id of the cart asking. What a cart can still buy.
+pub const AVAILABLE_STOCK: &str = "GREATEST(p.stock_quantity - COALESCE((
+        SELECT SUM(r.quantity) FROM stock_reservations r
+        WHERE r.product_id = p.id AND r.status = 'pending'
+        AND r.expires_at > CURRENT_TIMESTAMP AND r.cart_id IS DISTINCT FROM $1::uuid
+    ), 0), 0)::INT";
+
+/// Holds the stock for every line of the cart, replacing any earlier hold of
+/// the same cart. Returns `false`, reserving nothing, when other checkouts
+/// already hold too much of a product.
+pub async fn reserve(pool: &Pool<Postgres>, cart_id: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Replaced holds are canceled rather than released, so they stop counting
+    // against other checkouts for good.
+    sqlx::query(
+        "UPDATE stock_reservations SET status = 'canceled'
+        WHERE cart_id = $1::uuid AND status IN ('pending', 'released')",
+    )
+    .bind(cart_id)
+    .execute(&mut *tx)
+    .await?;
+
+    // Locking the product rows serializes concurrent checkouts of the same products.
+    let rows = sqlx::query(
+        "SELECT p.id, ci.quantity
+        FROM cart_items ci
+        JOIN products p ON p.id = ci.product_id
+        WHERE ci.cart_id = $1::uuid
+        ORDER BY p.id
+        FOR UPDATE OF p",
+    )
+    .bind(cart_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut wanted: Vec<(i32, i32)> = Vec::with_capacity(rows.len());
+    for row in rows {
+        wanted.push((row.try_get("id")?, row.try_get("quantity")?));
+    }
+    let product_ids: Vec<i32> = wanted.iter().map(|(id, _)| *id).collect();
+
+    let available: HashMap<i32, i32> = sqlx::query_as(&format!(
+        "SELECT p.id, {} FROM products p WHERE p.id = ANY($2)",
+        AVAILABLE_STOCK
+    ))
+    .bind(cart_id)
+    .bind(&product_ids)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+
+    let is_available = wanted
+        .iter()
+        .all(|(id, quantity)| *quantity <= available.get(id).copied().unwrap_or(0));
+    if !is_available {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+
+    sqlx::query(
+        "INSERT INTO stock_reservations (cart_id, expires_at, product_id, quantity)
+        SELECT $1::uuid, CURRENT_TIMESTAMP + make_interval(mins => $2), product_id, quantity
+        FROM cart_items
+        WHERE cart_id = $1::uuid",
+    )
+    .bind(cart_id)
+    .bind(RESERVATION_TTL_MINUTES)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Links the cart's pending reservations to the PaymentIntent that pays for them.
+pub async fn attach_payment_intent(
+    pool: &Pool<Postgres>,
+    cart_id: &str,
+    payment_intent_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE stock_reservations SET payment_intent_id = $2
+        WHERE cart_id = $1::uuid AND status = 'pending'",
+    )
+    .bind(cart_id)
+    .bind(payment_intent_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Takes the stock of a paid PaymentIntent's order, as part of the caller's
+/// transaction, and marks its reservations committed. The caller makes sure
+/// this runs once per order.
+///
+/// The order's items are what was paid for, so they are used rather than the
+/// reservations: a checkout that is still processing keeps its charge even
+/// when a later `reserve` of the same cart canceled its holds. Returns
+/// `false`, canceling the reservations and leaving stock untouched, when the
+/// stock was sold in the meantime.
+pub async fn commit(conn: &mut PgConnection, payment_intent_id: &str) -> Result<bool, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT oi.product_id, oi.quantity, p.stock_quantity
+        FROM order_items oi
+        JOIN orders o ON o.id = oi.order_id
+        JOIN products p ON p.id = oi.product_id
+        WHERE o.payment_intent_id = $1
+        ORDER BY oi.product_id
+        FOR UPDATE OF p",
+    )
+    .bind(payment_intent_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // Stock is checked against each product's total, as several lines of the
+    // order may be for the same product.
+    let mut wanted: HashMap<i32, (i64, i32)> = HashMap::new();
+    for row in rows {
+        let (quantity, stock_quantity): (i32, i32) =
+            (row.try_get("quantity")?, row.try_get("stock_quantity")?);
+        let entry = wanted
+            .entry(row.try_get("product_id")?)
+            .or_insert((0, stock_quantity));
+        entry.0 += quantity as i64;
+    }
+
+    let oversold = wanted
+        .iter()
+        .find(|(_, (quantity, stock_quantity))| *quantity > *stock_quantity as i64);
+    if let Some((product_id, (quantity, stock_quantity))) = oversold {
+        eprintln!(
+            "[inventory] PaymentIntent {} oversold product {} by {}",
+            payment_intent_id,
+            product_id,
+            quantity - *stock_quantity as i64
+        );
+        sqlx::query(
+            "UPDATE stock_reservations SET status = 'canceled'
+            WHERE payment_intent_id = $1 AND status IN ('pending', 'released')",
+        )
+        .bind(payment_intent_id)
+        .execute(&mut *conn)
+        .await?;
+        return Ok(false);
+    }
+
+    for (product_id, (quantity, _)) in wanted {
+        sqlx::query(
+            "UPDATE products
+            SET stock_quantity = stock_quantity - $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1",
+        )
+        .bind(product_id)
+        .bind(quantity as i32)
+        .execute(&mut *conn)
+        .await?;
+    }
+    sqlx::query(
+        "UPDATE stock_reservations SET status = 'committed'
+        WHERE payment_intent_id = $1 AND status IN ('pending', 'released')",
+    )
+    .bind(payment_intent_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(true)
+}
+
+/// Gives back the stock held for a PaymentIntent that failed or was canceled.
+pub async fn release(pool: &Pool<Postgres>, payment_intent_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE stock_reservations SET status = 'released'
+        WHERE payment_intent_id = $1 AND status = 'pending'",
+    )
+    .bind(payment_intent_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks reservations past their expiry as released; returns how many.
+pub async fn release_expired(pool: &Pool<Postgres>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE stock_reservations SET status = 'released'
+        WHERE status = 'pending' AND expires_at <= CURRENT_TIMESTAMP",
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
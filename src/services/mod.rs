@@ -0,0 +1,6 @@
+pub mod cart;
+pub mod catalog;
+pub mod inventory;
+pub mod orders;
+pub mod payments;
+pub mod shipping;
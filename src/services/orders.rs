@@ -0,0 +1,421 @@
+use super::cart::CartLine;
+use super::inventory;
+use super::shipping::Address;
+use crate::utils::money::Money;
+use serde::Serialize;
+use sqlx::{PgConnection, Pool, Postgres, QueryBuilder, Row};
+use std::str::FromStr;
+use stripe::Currency;
+
+#[derive(Serialize)]
+pub struct OrderItem {
+    pub product_id: Option<i32>,
+    pub product_name: String,
+    pub quantity: i32,
+    pub total_price: Money,
+    pub unit_price: Money,
+}
+
+/// How to reach the customer about an order, as entered at checkout.
+#[derive(Clone, Debug, Serialize)]
+pub struct Contact {
+    pub email: String,
+    pub name: String,
+    pub phone: String,
+}
+
+impl Contact {
+    /// Validates and normalizes the checkout's contact fields, returning a
+    /// message for the customer when one is unusable.
+    pub fn parse(name: &str, email: &str, phone: &str) -> Result<Self, String> {
+        let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if name.is_empty() || name.chars().count() > 255 {
+            return Err("Please enter your full name".to_string());
+        }
+
+        let email = email.trim();
+        let is_email = match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+            }
+            None => false,
+        };
+        if !is_email || email.len() > 255 || email.chars().any(char::is_whitespace) {
+            return Err("Please enter a valid email address".to_string());
+        }
+
+        let phone = phone.trim();
+        let digits = phone.chars().filter(char::is_ascii_digit).count();
+        let is_phone = phone
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c.is_ascii_digit() || " -.()".contains(c) || (c == '+' && i == 0));
+        if !is_phone || !(7..=15).contains(&digits) || phone.len() > 50 {
+            return Err("Please enter a valid phone number".to_string());
+        }
+
+        Ok(Contact {
+            email: email.to_string(),
+            name,
+            phone: phone.to_string(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct Order {
+    pub cart_id: Option<String>,
+    pub created_at: String,
+    pub customer: Option<Contact>,
+    pub id: i32,
+    pub items: Vec<OrderItem>,
+    pub payment_intent_id: String,
+    pub shipping: Option<Address>,
+    pub status: String,
+    pub total: Money,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OrderStatus {
+    Failed,
+    Paid,
+    Pending,
+    RefundPending,
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Failed => "failed",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Pending => "pending",
+            OrderStatus::RefundPending => "refund_pending",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+}
+
+fn parse_currency(code: &str) -> Result<Currency, sqlx::Error> {
+    Currency::from_str(&code.to_lowercase())
+        .map_err(|_| sqlx::Error::Decode(format!("Unknown order currency `{}`", code).into()))
+}
+
+enum OrderKey<'a> {
+    Id(i32),
+    PaymentIntent(&'a str),
+}
+
+async fn fetch(pool: &Pool<Postgres>, key: OrderKey<'_>) -> Result<Option<Order>, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "SELECT cart_id::text, to_char(created_at, 'YYYY-MM-DD HH24:MI') AS created_at,
+        currency, customer_email, customer_name, customer_phone, id, payment_intent_id,
+        shipping_city, shipping_country, shipping_line1, shipping_line2, shipping_postal_code,
+        shipping_state, status, total_cents
+        FROM orders
+        WHERE ",
+    );
+    match key {
+        OrderKey::Id(id) => builder.push("id = ").push_bind(id),
+        OrderKey::PaymentIntent(payment_intent_id) => builder
+            .push("payment_intent_id = ")
+            .push_bind(payment_intent_id.to_string()),
+    };
+    let row = match builder.build().fetch_optional(pool).await? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let id: i32 = row.try_get("id")?;
+    let currency = parse_currency(&row.try_get::<String, _>("currency")?)?;
+    let items = sqlx::query(
+        "SELECT product_id, product_name, quantity, total_price_cents, unit_price_cents
+        FROM order_items
+        WHERE order_id = $1
+        ORDER BY id",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|item| {
+        Ok(OrderItem {
+            product_id: item.try_get("product_id")?,
+            product_name: item.try_get("product_name")?,
+            quantity: item.try_get("quantity")?,
+            total_price: Money::new(item.try_get("total_price_cents")?, currency),
+            unit_price: Money::new(item.try_get("unit_price_cents")?, currency),
+        })
+    })
+    .collect::<Result<Vec<OrderItem>, sqlx::Error>>()?;
+
+    let customer = match (
+        row.try_get::<Option<String>, _>("customer_email")?,
+        row.try_get::<Option<String>, _>("customer_name")?,
+        row.try_get::<Option<String>, _>("customer_phone")?,
+    ) {
+        (Some(email), Some(name), Some(phone)) => Some(Contact { email, name, phone }),
+        _ => None,
+    };
+    let shipping = match (
+        row.try_get::<Option<String>, _>("shipping_city")?,
+        row.try_get::<Option<String>, _>("shipping_country")?,
+        row.try_get::<Option<String>, _>("shipping_line1")?,
+        row.try_get::<Option<String>, _>("shipping_postal_code")?,
+    ) {
+        (Some(city), Some(country), Some(line1), Some(postal_code)) => Some(Address {
+            city,
+            country,
+            line1,
+            line2: row.try_get("shipping_line2")?,
+            postal_code,
+            state: row.try_get("shipping_state")?,
+        }),
+        _ => None,
+    };
+
+    Ok(Some(Order {
+        cart_id: row.try_get("cart_id")?,
+        created_at: row
+            .try_get::<Option<String>, _>("created_at")?
+            .unwrap_or_default(),
+        customer,
+        id,
+        items,
+        payment_intent_id: row.try_get("payment_intent_id")?,
+        shipping,
+        status: row.try_get("status")?,
+        total: Money::new(row.try_get("total_cents")?, currency),
+    }))
+}
+
+pub async fn find(pool: &Pool<Postgres>, id: i32) -> Result<Option<Order>, sqlx::Error> {
+    fetch(pool, OrderKey::Id(id)).await
+}
+
+pub async fn find_by_payment_intent(
+    pool: &Pool<Postgres>,
+    payment_intent_id: &str,
+) -> Result<Option<Order>, sqlx::Error> {
+    fetch(pool, OrderKey::PaymentIntent(payment_intent_id)).await
+}
+
+/// The cart's latest order that has not been paid yet, if any.
+pub async fn find_open_for_cart(
+    pool: &Pool<Postgres>,
+    cart_id: &str,
+) -> Result<Option<Order>, sqlx::Error> {
+    let id: Option<i32> = sqlx::query_scalar(
+        "SELECT id FROM orders
+        WHERE cart_id = $1::uuid AND status IN ($2, $3)
+        ORDER BY id DESC
+        LIMIT 1",
+    )
+    .bind(cart_id)
+    .bind(OrderStatus::Pending.as_str())
+    .bind(OrderStatus::Failed.as_str())
+    .fetch_optional(pool)
+    .await?;
+    match id {
+        Some(id) => find(pool, id).await,
+        None => Ok(None),
+    }
+}
+
+/// Number of orders the cart has placed so far, paid or not.
+pub async fn count_for_cart(pool: &Pool<Postgres>, cart_id: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM orders WHERE cart_id = $1::uuid")
+        .bind(cart_id)
+        .fetch_one(pool)
+        .await
+}
+
+/// Records the pending order for the PaymentIntent that pays for `lines`,
+/// snapshotting names and prices so later catalog edits don't rewrite it.
+/// When the intent is reused for a changed cart, its order is rewritten.
+pub async fn save(
+    pool: &Pool<Postgres>,
+    cart_id: &str,
+    lines: &[CartLine],
+    total: Money,
+    payment_intent_id: &str,
+) -> Result<i32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let order_id: i32 = sqlx::query_scalar(
+        "INSERT INTO orders (cart_id, currency, payment_intent_id, status, total_cents)
+        VALUES ($1::uuid, $2, $3, $4, $5)
+        ON CONFLICT (payment_intent_id) DO UPDATE
+        SET currency = EXCLUDED.currency,
+            status = EXCLUDED.status,
+            total_cents = EXCLUDED.total_cents,
+            updated_at = CURRENT_TIMESTAMP
+        RETURNING id",
+    )
+    .bind(cart_id)
+    .bind(total.currency().to_string().to_uppercase())
+    .bind(payment_intent_id)
+    .bind(OrderStatus::Pending.as_str())
+    .bind(total.minor_units())
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM order_items WHERE order_id = $1")
+        .bind(order_id)
+        .execute(&mut *tx)
+        .await?;
+    for line in lines.iter().filter(|line| line.is_active) {
+        sqlx::query(
+            "INSERT INTO order_items
+            (order_id, product_id, product_name, quantity, total_price_cents, unit_price_cents)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(order_id)
+        .bind(line.id)
+        .bind(&line.name)
+        .bind(line.quantity)
+        .bind(line.total_price_item.minor_units())
+        .bind(line.price.minor_units())
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(order_id)
+}
+
+/// Stores the customer's contact details and shipping address on an order
+/// that is still open. Returns whether the order changed.
+pub async fn set_customer(
+    pool: &Pool<Postgres>,
+    payment_intent_id: &str,
+    contact: &Contact,
+    shipping: &Address,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE orders
+        SET customer_email = $2, customer_name = $3, customer_phone = $4,
+            shipping_city = $5, shipping_country = $6, shipping_line1 = $7,
+            shipping_line2 = $8, shipping_postal_code = $9, shipping_state = $10,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE payment_intent_id = $1 AND status IN ($11, $12)",
+    )
+    .bind(payment_intent_id)
+    .bind(&contact.email)
+    .bind(&contact.name)
+    .bind(&contact.phone)
+    .bind(&shipping.city)
+    .bind(&shipping.country)
+    .bind(&shipping.line1)
+    .bind(&shipping.line2)
+    .bind(&shipping.postal_code)
+    .bind(&shipping.state)
+    .bind(OrderStatus::Pending.as_str())
+    .bind(OrderStatus::Failed.as_str())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks the order paid; a failed attempt may still succeed on the same
+/// PaymentIntent. Returns whether the order changed.
+pub async fn mark_paid(
+    conn: &mut PgConnection,
+    payment_intent_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE orders
+        SET status = $2, paid_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+        WHERE payment_intent_id = $1 AND status IN ($3, $4)",
+    )
+    .bind(payment_intent_id)
+    .bind(OrderStatus::Paid.as_str())
+    .bind(OrderStatus::Pending.as_str())
+    .bind(OrderStatus::Failed.as_str())
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks a still-pending order failed. Returns whether the order changed.
+pub async fn mark_failed(
+    pool: &Pool<Postgres>,
+    payment_intent_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE orders SET status = $2, updated_at = CURRENT_TIMESTAMP
+        WHERE payment_intent_id = $1 AND status = $3",
+    )
+    .bind(payment_intent_id)
+    .bind(OrderStatus::Failed.as_str())
+    .bind(OrderStatus::Pending.as_str())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks a paid order, or one waiting for its refund, refunded. Returns
+/// whether the order changed.
+pub async fn mark_refunded(
+    pool: &Pool<Postgres>,
+    payment_intent_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE orders SET status = $2, updated_at = CURRENT_TIMESTAMP
+        WHERE payment_intent_id = $1 AND status IN ($3, $4)",
+    )
+    .bind(payment_intent_id)
+    .bind(OrderStatus::Refunded.as_str())
+    .bind(OrderStatus::Paid.as_str())
+    .bind(OrderStatus::RefundPending.as_str())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks the order paid and takes the reserved stock. Returns `false` while
+/// the order has to be refunded because the stock ran out before the payment
+/// went through. Safe to repeat, as both the return page and the webhook
+/// report the same payment: the stock is only taken by the call that marks
+/// the order paid, in the same transaction, so it is never taken twice or not
+/// at all. A sold-out order is left `refund_pending` until `mark_refunded`,
+/// so a refund that fails is attempted again.
+pub async fn record_payment_success(
+    pool: &Pool<Postgres>,
+    payment_intent_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if mark_paid(&mut tx, payment_intent_id).await?
+        && !inventory::commit(&mut tx, payment_intent_id).await?
+    {
+        sqlx::query(
+            "UPDATE orders SET status = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE payment_intent_id = $1",
+        )
+        .bind(payment_intent_id)
+        .bind(OrderStatus::RefundPending.as_str())
+        .execute(&mut *tx)
+        .await?;
+    }
+    let status: Option<String> =
+        sqlx::query_scalar("SELECT status FROM orders WHERE payment_intent_id = $1")
+            .bind(payment_intent_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    tx.commit().await?;
+    Ok(status.as_deref() != Some(OrderStatus::RefundPending.as_str()))
+}
+
+/// Gives back the reserved stock and marks the order failed. Safe to repeat.
+pub async fn record_payment_failure(
+    pool: &Pool<Postgres>,
+    payment_intent_id: &str,
+) -> Result<(), sqlx::Error> {
+    inventory::release(pool, payment_intent_id).await?;
+    mark_failed(pool, payment_intent_id).await?;
+    Ok(())
+}
@@ -0,0 +1,291 @@
+use super::{Intent, IntentStatus, NewIntent, PaymentEvent, PaymentProvider};
+use crate::errors::AppError;
+use crate::services::orders::Contact;
+use crate::services::shipping::Address;
+use futures::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// In-process stand-in for Stripe, for local development and offline runs.
+///
+/// Intents live in memory; the payment page confirms or declines them through
+/// `confirm`, which reports the same events Stripe's webhook would.
+#[derive(Default)]
+pub struct MockPaymentProvider {
+    idempotency_keys: Mutex<HashMap<String, String>>,
+    intents: Mutex<HashMap<String, MockIntent>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+struct MockIntent {
+    /// Whether `set_customer` went through, which the payment page does
+    /// before confirming.
+    has_customer: bool,
+    status: IntentStatus,
+}
+
+impl MockPaymentProvider {
+    fn intent(&self, id: &str) -> Result<MockIntent, AppError> {
+        self.intents
+            .lock()
+            .expect("mock payment intents lock poisoned")
+            .get(id)
+            .copied()
+            .ok_or_else(|| AppError::Payment(format!("Unknown mock PaymentIntent `{}`", id)))
+    }
+
+    fn status(&self, id: &str) -> Result<IntentStatus, AppError> {
+        Ok(self.intent(id)?.status)
+    }
+
+    fn set_intent(&self, id: &str, intent: MockIntent) {
+        self.intents
+            .lock()
+            .expect("mock payment intents lock poisoned")
+            .insert(id.to_string(), intent);
+    }
+
+    fn set_status(&self, id: &str, status: IntentStatus) -> Result<(), AppError> {
+        let intent = self.intent(id)?;
+        self.set_intent(id, MockIntent { status, ..intent });
+        Ok(())
+    }
+
+    /// Settles a pending intent as a test card would. Like Stripe's payment
+    /// form, it refuses to pay before the customer's details are saved.
+    pub fn confirm(&self, id: &str, succeed: bool) -> Result<PaymentEvent, AppError> {
+        let intent = self.intent(id)?;
+        match intent.status {
+            IntentStatus::Succeeded => Err(AppError::Validation(
+                "This payment has already been made".to_string(),
+            )),
+            _ if !intent.has_customer => Err(AppError::Validation(
+                "Please enter your contact details and shipping address first".to_string(),
+            )),
+            _ if succeed => {
+                self.set_status(id, IntentStatus::Succeeded)?;
+                Ok(PaymentEvent::Succeeded(id.to_string()))
+            }
+            _ => {
+                self.set_status(id, IntentStatus::Failed)?;
+                Ok(PaymentEvent::Failed(id.to_string()))
+            }
+        }
+    }
+}
+
+impl PaymentProvider for MockPaymentProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn public_key(&self) -> &str {
+        "pk_mock"
+    }
+
+    fn create_intent<'a>(
+        &'a self,
+        intent: NewIntent<'a>,
+    ) -> LocalBoxFuture<'a, Result<Intent, AppError>> {
+        Box::pin(async move {
+            let mut idempotency_keys = self
+                .idempotency_keys
+                .lock()
+                .expect("mock idempotency keys lock poisoned");
+            if let Some(id) = idempotency_keys.get(&intent.idempotency_key) {
+                return Ok(Intent {
+                    client_secret: Some(format!("{}_secret_mock", id)),
+                    id: id.clone(),
+                    status: self.status(id)?,
+                });
+            }
+
+            // Prefixed with the start time so ids stay unique across restarts.
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default();
+            let id = format!(
+                "pi_mock_{}_{}",
+                started,
+                self.next_id.fetch_add(1, Ordering::Relaxed)
+            );
+            self.set_intent(
+                &id,
+                MockIntent {
+                    has_customer: false,
+                    status: IntentStatus::Incomplete,
+                },
+            );
+            idempotency_keys.insert(intent.idempotency_key, id.clone());
+            Ok(Intent {
+                client_secret: Some(format!("{}_secret_mock", id)),
+                id,
+                status: IntentStatus::Incomplete,
+            })
+        })
+    }
+
+    fn retrieve_intent<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<Intent, AppError>> {
+        Box::pin(async move {
+            Ok(Intent {
+                client_secret: None,
+                id: id.to_string(),
+                status: self.status(id)?,
+            })
+        })
+    }
+
+    fn update_intent<'a>(
+        &'a self,
+        id: &'a str,
+        _intent: NewIntent<'a>,
+    ) -> LocalBoxFuture<'a, Result<Intent, AppError>> {
+        Box::pin(async move {
+            let status = self.status(id)?;
+            if !status.is_open() {
+                return Err(AppError::Payment(format!(
+                    "Mock PaymentIntent `{}` can no longer be updated",
+                    id
+                )));
+            }
+            Ok(Intent {
+                client_secret: Some(format!("{}_secret_mock", id)),
+                id: id.to_string(),
+                status,
+            })
+        })
+    }
+
+    fn set_customer<'a>(
+        &'a self,
+        id: &'a str,
+        _contact: &'a Contact,
+        _shipping: &'a Address,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let intent = self.intent(id)?;
+            if !intent.status.is_open() {
+                return Err(AppError::Payment(format!(
+                    "Mock PaymentIntent `{}` can no longer be updated",
+                    id
+                )));
+            }
+            self.set_intent(
+                id,
+                MockIntent {
+                    has_customer: true,
+                    ..intent
+                },
+            );
+            Ok(())
+        })
+    }
+
+    fn refund<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            match self.status(id)? {
+                IntentStatus::Succeeded => Ok(()),
+                _ => Err(AppError::Payment(format!(
+                    "Mock PaymentIntent `{}` has not succeeded",
+                    id
+                ))),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::money::Money;
+    use futures::executor::block_on;
+    use stripe::Currency;
+
+    fn new_intent(idempotency_key: &str) -> NewIntent<'static> {
+        NewIntent {
+            amount: Money::new(2128, Currency::EUR),
+            description: "Mug (x2)",
+            idempotency_key: idempotency_key.to_string(),
+        }
+    }
+
+    fn set_customer(provider: &MockPaymentProvider, id: &str) -> Result<(), AppError> {
+        let contact = Contact::parse("Ada Lovelace", "ada@example.com", "+44 20 7946 0000")
+            .expect("valid contact");
+        let shipping = Address::parse("GB", "10 Downing St", "", "London", "", "SW1A 2AA")
+            .expect("valid address");
+        block_on(provider.set_customer(id, &contact, &shipping))
+    }
+
+    #[test]
+    fn create_intent_reuses_the_intent_of_an_idempotency_key() {
+        let provider = MockPaymentProvider::default();
+        let first = block_on(provider.create_intent(new_intent("checkout-1"))).unwrap();
+        let retried = block_on(provider.create_intent(new_intent("checkout-1"))).unwrap();
+        let other = block_on(provider.create_intent(new_intent("checkout-2"))).unwrap();
+
+        assert_eq!(first.id, retried.id);
+        assert_ne!(first.id, other.id);
+        assert_eq!(first.status, IntentStatus::Incomplete);
+        assert!(first.client_secret.is_some());
+    }
+
+    #[test]
+    fn unknown_intents_are_errors() {
+        let provider = MockPaymentProvider::default();
+        assert!(block_on(provider.retrieve_intent("pi_mock_gone")).is_err());
+        assert!(provider.confirm("pi_mock_gone", true).is_err());
+    }
+
+    #[test]
+    fn confirm_requires_the_customer_details() {
+        let provider = MockPaymentProvider::default();
+        let intent = block_on(provider.create_intent(new_intent("checkout-1"))).unwrap();
+
+        assert!(matches!(
+            provider.confirm(&intent.id, true),
+            Err(AppError::Validation(_))
+        ));
+        set_customer(&provider, &intent.id).unwrap();
+        assert!(matches!(
+            provider.confirm(&intent.id, true),
+            Ok(PaymentEvent::Succeeded(id)) if id == intent.id
+        ));
+    }
+
+    #[test]
+    fn declined_intents_can_be_paid_again() {
+        let provider = MockPaymentProvider::default();
+        let intent = block_on(provider.create_intent(new_intent("checkout-1"))).unwrap();
+        set_customer(&provider, &intent.id).unwrap();
+
+        assert!(matches!(
+            provider.confirm(&intent.id, false),
+            Ok(PaymentEvent::Failed(_))
+        ));
+        let declined = block_on(provider.retrieve_intent(&intent.id)).unwrap();
+        assert_eq!(declined.status, IntentStatus::Failed);
+        assert!(declined.status.is_open());
+        assert!(block_on(provider.update_intent(&intent.id, new_intent(""))).is_ok());
+        assert!(provider.confirm(&intent.id, true).is_ok());
+    }
+
+    #[test]
+    fn succeeded_intents_are_closed_and_refundable() {
+        let provider = MockPaymentProvider::default();
+        let intent = block_on(provider.create_intent(new_intent("checkout-1"))).unwrap();
+        assert!(block_on(provider.refund(&intent.id)).is_err());
+
+        set_customer(&provider, &intent.id).unwrap();
+        provider.confirm(&intent.id, true).unwrap();
+
+        assert!(provider.confirm(&intent.id, true).is_err());
+        assert!(block_on(provider.update_intent(&intent.id, new_intent(""))).is_err());
+        assert!(set_customer(&provider, &intent.id).is_err());
+        assert!(block_on(provider.refund(&intent.id)).is_ok());
+    }
+}
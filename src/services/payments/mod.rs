@@ -0,0 +1,156 @@
+pub mod mock_provider;
+pub mod stripe_provider;
+
+use super::orders;
+use super::orders::Contact;
+use super::shipping::Address;
+use crate::config::PaymentConfig;
+use crate::errors::AppError;
+use crate::utils::money::Money;
+use futures::future::LocalBoxFuture;
+use mock_provider::MockPaymentProvider;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use stripe_provider::StripePaymentProvider;
+
+/// Where a payment stands, as far as the checkout cares.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IntentStatus {
+    Canceled,
+    /// Declined; the customer may try again on the same intent.
+    Failed,
+    /// Waiting on the customer, e.g. an abandoned 3-D Secure step.
+    Incomplete,
+    Processing,
+    Succeeded,
+}
+
+pub struct Intent {
+    pub client_secret: Option<String>,
+    pub id: String,
+    pub status: IntentStatus,
+}
+
+impl IntentStatus {
+    /// Whether the intent can still be updated and paid.
+    pub fn is_open(&self) -> bool {
+        matches!(self, IntentStatus::Failed | IntentStatus::Incomplete)
+    }
+}
+
+pub struct NewIntent<'a> {
+    pub amount: Money,
+    pub description: &'a str,
+    /// Sent on creation so a retried request returns the same intent.
+    pub idempotency_key: String,
+}
+
+/// A payment state change reported by the provider, e.g. via a webhook.
+#[derive(Debug)]
+pub enum PaymentEvent {
+    Failed(String),
+    Refunded(String),
+    Succeeded(String),
+}
+
+/// Payment service provider behind the checkout.
+pub trait PaymentProvider: Send + Sync {
+    /// Short name the payment page uses to pick its client-side form.
+    fn name(&self) -> &'static str;
+
+    /// Publishable key handed to the client-side form.
+    fn public_key(&self) -> &str;
+
+    fn create_intent<'a>(
+        &'a self,
+        intent: NewIntent<'a>,
+    ) -> LocalBoxFuture<'a, Result<Intent, AppError>>;
+
+    fn retrieve_intent<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<Intent, AppError>>;
+
+    /// Changes the amount and description of an open intent.
+    fn update_intent<'a>(
+        &'a self,
+        id: &'a str,
+        intent: NewIntent<'a>,
+    ) -> LocalBoxFuture<'a, Result<Intent, AppError>>;
+
+    /// Attaches the customer's contact details and shipping address to an
+    /// open intent, so the receipt goes to them and the payment can be found
+    /// by their email.
+    fn set_customer<'a>(
+        &'a self,
+        id: &'a str,
+        contact: &'a Contact,
+        shipping: &'a Address,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>>;
+
+    /// Refunds the full amount of a succeeded intent.
+    fn refund<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), AppError>>;
+}
+
+/// The provider picked by `PAYMENT_PROVIDER` (`stripe`, the default, or `mock`).
+///
+/// The mock is also kept on its own so its test-payment endpoint can reach it.
+pub struct Providers {
+    pub mock: Option<Arc<MockPaymentProvider>>,
+    pub provider: Arc<dyn PaymentProvider>,
+}
+
+pub fn from_config(config: &PaymentConfig) -> Providers {
+    match config {
+        PaymentConfig::Stripe(stripe) => Providers {
+            mock: None,
+            provider: Arc::new(StripePaymentProvider::new(stripe)),
+        },
+        PaymentConfig::Mock { .. } => {
+            let mock = Arc::new(MockPaymentProvider::default());
+            Providers {
+                mock: Some(mock.clone()),
+                provider: mock,
+            }
+        }
+    }
+}
+
+/// Idempotency key for creating the intent of a cart's `attempt`-th checkout.
+///
+/// It covers what is charged, so retrying the same checkout reuses the intent
+/// while a changed cart never collides with an earlier request.
+pub fn idempotency_key(cart_id: &str, attempt: i64, intent: &NewIntent<'_>) -> String {
+    let mut digest = Sha256::new();
+    digest.update(intent.amount.currency().to_string().as_bytes());
+    digest.update(intent.amount.minor_units().to_be_bytes());
+    digest.update(intent.description.as_bytes());
+    format!(
+        "checkout-{}-{}-{}",
+        cart_id,
+        attempt,
+        &hex::encode(digest.finalize())[..16]
+    )
+}
+
+/// Applies a payment event to the order and its stock, refunding payments
+/// whose stock sold out in the meantime. Safe to repeat.
+pub async fn apply_event(
+    pool: &Pool<Postgres>,
+    provider: &dyn PaymentProvider,
+    event: &PaymentEvent,
+) -> Result<(), AppError> {
+    match event {
+        PaymentEvent::Failed(payment_intent_id) => {
+            orders::record_payment_failure(pool, payment_intent_id).await?;
+        }
+        PaymentEvent::Refunded(payment_intent_id) => {
+            orders::mark_refunded(pool, payment_intent_id).await?;
+        }
+        PaymentEvent::Succeeded(payment_intent_id) => {
+            if !orders::record_payment_success(pool, payment_intent_id).await? {
+                provider.refund(payment_intent_id).await?;
+                orders::mark_refunded(pool, payment_intent_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
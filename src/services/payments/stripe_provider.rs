@@ -0,0 +1,142 @@
+use super::{Intent, IntentStatus, NewIntent, PaymentProvider};
+use crate::config::StripeConfig;
+use crate::errors::AppError;
+use crate::services::orders::Contact;
+use crate::services::shipping::Address;
+use futures::future::LocalBoxFuture;
+use stripe::{
+    Client, CreatePaymentIntent, CreateRefund, Metadata, PaymentIntent, PaymentIntentId,
+    PaymentIntentStatus, Refund, RequestStrategy, UpdatePaymentIntent, UpdatePaymentIntentShipping,
+    UpdatePaymentIntentShippingAddress,
+};
+
+pub struct StripePaymentProvider {
+    client: Client,
+    public_key: String,
+}
+
+impl StripePaymentProvider {
+    pub fn new(config: &StripeConfig) -> Self {
+        StripePaymentProvider {
+            client: Client::new(config.private_key.clone()),
+            public_key: config.public_key.clone(),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<PaymentIntentId, AppError> {
+    id.parse::<PaymentIntentId>()
+        .map_err(|_| AppError::Validation(format!("Invalid PaymentIntent id `{}`", id)))
+}
+
+fn to_intent(payment_intent: PaymentIntent) -> Intent {
+    let status = match payment_intent.status {
+        PaymentIntentStatus::Succeeded => IntentStatus::Succeeded,
+        PaymentIntentStatus::Processing | PaymentIntentStatus::RequiresCapture => {
+            IntentStatus::Processing
+        }
+        PaymentIntentStatus::Canceled => IntentStatus::Canceled,
+        PaymentIntentStatus::RequiresPaymentMethod => IntentStatus::Failed,
+        PaymentIntentStatus::RequiresAction | PaymentIntentStatus::RequiresConfirmation => {
+            IntentStatus::Incomplete
+        }
+    };
+    Intent {
+        client_secret: payment_intent.client_secret,
+        id: payment_intent.id.to_string(),
+        status,
+    }
+}
+
+impl PaymentProvider for StripePaymentProvider {
+    fn name(&self) -> &'static str {
+        "stripe"
+    }
+
+    fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    fn create_intent<'a>(
+        &'a self,
+        intent: NewIntent<'a>,
+    ) -> LocalBoxFuture<'a, Result<Intent, AppError>> {
+        Box::pin(async move {
+            let mut create_intent =
+                CreatePaymentIntent::new(intent.amount.minor_units(), intent.amount.currency());
+            create_intent.confirm = Some(false);
+            create_intent.description = Some(intent.description);
+            let client = self
+                .client
+                .clone()
+                .with_strategy(RequestStrategy::Idempotent(intent.idempotency_key.clone()));
+            let payment_intent = PaymentIntent::create(&client, create_intent).await?;
+            Ok(to_intent(payment_intent))
+        })
+    }
+
+    fn update_intent<'a>(
+        &'a self,
+        id: &'a str,
+        intent: NewIntent<'a>,
+    ) -> LocalBoxFuture<'a, Result<Intent, AppError>> {
+        Box::pin(async move {
+            let mut update_intent = UpdatePaymentIntent::new();
+            update_intent.amount = Some(intent.amount.minor_units());
+            update_intent.currency = Some(intent.amount.currency());
+            update_intent.description = Some(intent.description);
+            let payment_intent =
+                PaymentIntent::update(&self.client, &parse_id(id)?, update_intent).await?;
+            Ok(to_intent(payment_intent))
+        })
+    }
+
+    fn retrieve_intent<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<Intent, AppError>> {
+        Box::pin(async move {
+            let payment_intent = PaymentIntent::retrieve(&self.client, &parse_id(id)?, &[]).await?;
+            Ok(to_intent(payment_intent))
+        })
+    }
+
+    fn set_customer<'a>(
+        &'a self,
+        id: &'a str,
+        contact: &'a Contact,
+        shipping: &'a Address,
+    ) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let mut update_intent = UpdatePaymentIntent::new();
+            update_intent.receipt_email = Some(contact.email.clone());
+            update_intent.metadata = Some(Metadata::from([
+                ("customer_email".to_string(), contact.email.clone()),
+                ("customer_name".to_string(), contact.name.clone()),
+                ("customer_phone".to_string(), contact.phone.clone()),
+            ]));
+            update_intent.shipping = Some(UpdatePaymentIntentShipping {
+                address: UpdatePaymentIntentShippingAddress {
+                    city: Some(shipping.city.clone()),
+                    country: Some(shipping.country.clone()),
+                    line1: Some(shipping.line1.clone()),
+                    line2: shipping.line2.clone(),
+                    postal_code: Some(shipping.postal_code.clone()),
+                    state: shipping.state.clone(),
+                },
+                carrier: None,
+                name: contact.name.clone(),
+                phone: Some(contact.phone.clone()),
+                tracking_number: None,
+            });
+            PaymentIntent::update(&self.client, &parse_id(id)?, update_intent).await?;
+            Ok(())
+        })
+    }
+
+    fn refund<'a>(&'a self, id: &'a str) -> LocalBoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let mut create_refund = CreateRefund::new();
+            create_refund.payment_intent = Some(parse_id(id)?);
+            Refund::create(&self.client, create_refund).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,320 @@
+use serde::Serialize;
+
+/// A country the store ships to, and what its addresses must include.
+#[derive(Serialize)]
+pub struct Country {
+    pub code: &'static str,
+    pub name: &'static str,
+    /// Name of the region field when the country's addresses need one.
+    pub region: Option<&'static str>,
+    /// Accepted postal code shapes, `9` standing for a digit and `A` for a
+    /// letter. Spaces and dashes are ignored when matching, and codes are
+    /// stored with the shape's own.
+    #[serde(skip)]
+    postal_codes: &'static [&'static str],
+}
+
+pub const COUNTRIES: &[Country] = &[
+    Country {
+        code: "AU",
+        name: "Australia",
+        region: Some("State"),
+        postal_codes: &["9999"],
+    },
+    Country {
+        code: "CA",
+        name: "Canada",
+        region: Some("Province"),
+        postal_codes: &["A9A 9A9"],
+    },
+    Country {
+        code: "DE",
+        name: "Germany",
+        region: None,
+        postal_codes: &["99999"],
+    },
+    Country {
+        code: "ES",
+        name: "Spain",
+        region: None,
+        postal_codes: &["99999"],
+    },
+    Country {
+        code: "FR",
+        name: "France",
+        region: None,
+        postal_codes: &["99999"],
+    },
+    Country {
+        code: "GB",
+        name: "United Kingdom",
+        region: None,
+        postal_codes: &[
+            "A9 9AA", "A99 9AA", "A9A 9AA", "AA9 9AA", "AA99 9AA", "AA9A 9AA",
+        ],
+    },
+    Country {
+        code: "IT",
+        name: "Italy",
+        region: None,
+        postal_codes: &["99999"],
+    },
+    Country {
+        code: "JP",
+        name: "Japan",
+        region: Some("Prefecture"),
+        postal_codes: &["999-9999"],
+    },
+    Country {
+        code: "NL",
+        name: "Netherlands",
+        region: None,
+        postal_codes: &["9999 AA"],
+    },
+    Country {
+        code: "US",
+        name: "United States",
+        region: Some("State"),
+        postal_codes: &["99999", "99999-9999"],
+    },
+];
+
+pub fn find_country(code: &str) -> Option<&'static Country> {
+    COUNTRIES
+        .iter()
+        .find(|country| country.code.eq_ignore_ascii_case(code))
+}
+
+/// The postal code written in the form of `shape`, `None` when it doesn't fit.
+fn format_postal_code(postal_code: &str, shape: &str) -> Option<String> {
+    let mut postal_code = postal_code.chars().filter(|c| *c != ' ' && *c != '-');
+    let mut formatted = String::with_capacity(shape.len());
+    for expected in shape.chars() {
+        match expected {
+            '9' | 'A' => {
+                let c = postal_code.next()?;
+                let is_match = match expected {
+                    '9' => c.is_ascii_digit(),
+                    _ => c.is_ascii_alphabetic(),
+                };
+                if !is_match {
+                    return None;
+                }
+                formatted.push(c);
+            }
+            separator => formatted.push(separator),
+        }
+    }
+    match postal_code.next() {
+        None => Some(formatted),
+        Some(_) => None,
+    }
+}
+
+/// Where an order is shipped, as entered at checkout.
+#[derive(Clone, Debug, Serialize)]
+pub struct Address {
+    pub city: String,
+    pub country: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub postal_code: String,
+    pub state: Option<String>,
+}
+
+/// Trims and collapses the whitespace of a form field, `None` when it is empty.
+fn field(value: &str) -> Option<String> {
+    let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+    match value.is_empty() {
+        true => None,
+        false => Some(value),
+    }
+}
+
+impl Address {
+    /// Validates the checkout's address fields against the rules of the
+    /// country, returning a message for the customer when one is unusable.
+    pub fn parse(
+        country: &str,
+        line1: &str,
+        line2: &str,
+        city: &str,
+        state: &str,
+        postal_code: &str,
+    ) -> Result<Self, String> {
+        let country = find_country(country.trim())
+            .ok_or_else(|| "Please choose a country we ship to".to_string())?;
+
+        let line1 = field(line1)
+            .filter(|line1| line1.chars().count() <= 200)
+            .ok_or_else(|| "Please enter your street address".to_string())?;
+        let line2 = field(line2);
+        if line2
+            .as_ref()
+            .is_some_and(|line2| line2.chars().count() > 200)
+        {
+            return Err("The second address line is too long".to_string());
+        }
+        let city = field(city)
+            .filter(|city| city.chars().count() <= 100)
+            .ok_or_else(|| "Please enter your city".to_string())?;
+
+        let state = match country.region {
+            Some(region) => Some(
+                field(state)
+                    .filter(|state| state.chars().count() <= 100)
+                    .ok_or_else(|| format!("Please enter your {}", region.to_lowercase()))?,
+            ),
+            None => None,
+        };
+
+        let postal_code = field(postal_code)
+            .map(|postal_code| postal_code.to_uppercase())
+            .and_then(|postal_code| {
+                country
+                    .postal_codes
+                    .iter()
+                    .find_map(|shape| format_postal_code(&postal_code, shape))
+            })
+            .ok_or_else(|| format!("Please enter a valid postal code for {}", country.name))?;
+
+        Ok(Address {
+            city,
+            country: country.code.to_string(),
+            line1,
+            line2,
+            postal_code,
+            state,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn postal_code_is_valid(country: &str, postal_code: &str) -> bool {
+        find_country(country)
+            .unwrap()
+            .postal_codes
+            .iter()
+            .any(|shape| format_postal_code(postal_code, shape).is_some())
+    }
+
+    fn parse(country: &str, state: &str, postal_code: &str) -> Result<Address, String> {
+        Address::parse(country, "1 Main St", "", "Springfield", state, postal_code)
+    }
+
+    #[test]
+    fn gb_postcodes_match_every_outward_code_shape() {
+        for postal_code in [
+            "M1 1AA", "B33 8TH", "W1A 0AX", "CR2 6XH", "DN55 1PT", "EC1A 1BB",
+        ] {
+            assert!(postal_code_is_valid("GB", postal_code), "{}", postal_code);
+        }
+        for postal_code in ["M1 1A", "M1 1AAA", "1M1 1AA", "EC1A1 1BB", "M1 1A1", ""] {
+            assert!(!postal_code_is_valid("GB", postal_code), "{}", postal_code);
+        }
+    }
+
+    #[test]
+    fn ca_postal_codes_alternate_letters_and_digits() {
+        assert!(postal_code_is_valid("CA", "K1A 0B1"));
+        assert!(postal_code_is_valid("CA", "K1A0B1"));
+        assert!(!postal_code_is_valid("CA", "K1A 0B"));
+        assert!(!postal_code_is_valid("CA", "11A 0B1"));
+        assert!(!postal_code_is_valid("CA", "K1A 0B1 2"));
+    }
+
+    #[test]
+    fn us_postal_codes_are_zip_or_zip_plus_four() {
+        assert!(postal_code_is_valid("US", "94105"));
+        assert!(postal_code_is_valid("US", "94105-1234"));
+        assert!(!postal_code_is_valid("US", "9410"));
+        assert!(!postal_code_is_valid("US", "941056"));
+        assert!(!postal_code_is_valid("US", "94105-123"));
+        assert!(!postal_code_is_valid("US", "ABCDE"));
+    }
+
+    #[test]
+    fn other_countries_use_their_own_shapes() {
+        assert!(postal_code_is_valid("JP", "100-0001"));
+        assert!(postal_code_is_valid("NL", "1012 AB"));
+        assert!(postal_code_is_valid("DE", "10115"));
+        assert!(postal_code_is_valid("AU", "2000"));
+        assert!(!postal_code_is_valid("NL", "1012"));
+        assert!(!postal_code_is_valid("AU", "20000"));
+    }
+
+    #[test]
+    fn countries_with_regions_require_one() {
+        assert_eq!(
+            parse("US", " ", "94105").unwrap_err(),
+            "Please enter your state"
+        );
+        assert_eq!(
+            parse("CA", "", "K1A 0B1").unwrap_err(),
+            "Please enter your province"
+        );
+        assert_eq!(
+            parse("JP", "", "100-0001").unwrap_err(),
+            "Please enter your prefecture"
+        );
+        assert_eq!(
+            parse("US", "CA", "94105").unwrap().state.as_deref(),
+            Some("CA")
+        );
+        assert_eq!(parse("GB", "Kent", "CR2 6XH").unwrap().state, None);
+    }
+
+    #[test]
+    fn parse_normalizes_the_address() {
+        let address = Address::parse(
+            "ca",
+            "  24  Sussex   Drive ",
+            " ",
+            "Ottawa",
+            "Ontario",
+            "k1m 1m4",
+        )
+        .unwrap();
+
+        assert_eq!(address.country, "CA");
+        assert_eq!(address.line1, "24 Sussex Drive");
+        assert_eq!(address.line2, None);
+        assert_eq!(address.postal_code, "K1M 1M4");
+    }
+
+    #[test]
+    fn parse_rejects_unknown_countries_and_bad_postal_codes() {
+        assert_eq!(
+            parse("XX", "", "12345").unwrap_err(),
+            "Please choose a country we ship to"
+        );
+        assert_eq!(
+            parse("GB", "", "12345").unwrap_err(),
+            "Please enter a valid postal code for United Kingdom"
+        );
+        assert_eq!(
+            parse("US", "CA", "").unwrap_err(),
+            "Please enter a valid postal code for United States"
+        );
+    }
+
+    #[test]
+    fn postal_codes_are_stored_in_the_shape_of_their_country() {
+        let postal_code = |country: &str, state: &str, postal_code: &str| {
+            parse(country, state, postal_code).unwrap().postal_code
+        };
+
+        assert_eq!(postal_code("CA", "Ontario", "K1A0B1"), "K1A 0B1");
+        assert_eq!(postal_code("CA", "Ontario", "k1a-0b1"), "K1A 0B1");
+        assert_eq!(postal_code("US", "CA", "9-4-1-0-5"), "94105");
+        assert_eq!(postal_code("US", "CA", "941051234"), "94105-1234");
+        assert_eq!(postal_code("JP", "Tokyo", "1000001"), "100-0001");
+        assert_eq!(postal_code("GB", "", "ec1a1bb"), "EC1A 1BB");
+        // Any run of separators is dropped, so the stored code stays short.
+        let padded = format!("941{}05", "-".repeat(40));
+        assert_eq!(postal_code("US", "CA", &padded), "94105");
+    }
+}
@@ -0,0 +1,180 @@
+use super::money::{format_amount, minor_unit_exponent};
+use crate::errors::AppError;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use serde::Serialize;
+use sqlx::{Pool, Postgres, Row};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use stripe::Currency;
+use tera::Value;
+
+pub const DISPLAY_CURRENCY_COOKIE: &str = "display_currency";
+
+/// Currency the catalog is priced and charged in, from `STORE_CURRENCY`.
+#[derive(Clone, Copy)]
+pub struct StoreCurrency(pub Currency);
+
+impl StoreCurrency {
+    pub fn parse(code: &str) -> Result<Self, String> {
+        Currency::from_str(&code.to_lowercase())
+            .map(StoreCurrency)
+            .map_err(|_| format!("{} is not a currency code", code))
+    }
+}
+
+impl Default for StoreCurrency {
+    fn default() -> Self {
+        StoreCurrency(Currency::EUR)
+    }
+}
+
+/// How long loaded exchange rates are reused before they are read again.
+const RATES_TTL: Duration = Duration::from_secs(60);
+
+/// The store currency at a rate of 1, then every other usable rate.
+type Rates = Arc<Vec<(Currency, f64)>>;
+
+/// Rates of the `exchange_rates` table shared by every request, so pages don't
+/// each query it. Edits to the table show up within `RATES_TTL`.
+#[derive(Default)]
+pub struct ExchangeRates {
+    loaded: Mutex<Option<(Instant, Rates)>>,
+}
+
+impl ExchangeRates {
+    async fn get(&self, pool: &Pool<Postgres>, store: Currency) -> Result<Rates, sqlx::Error> {
+        if let Some((loaded_at, rates)) =
+            &*self.loaded.lock().expect("exchange rates lock poisoned")
+        {
+            if loaded_at.elapsed() < RATES_TTL {
+                return Ok(rates.clone());
+            }
+        }
+
+        let rows = sqlx::query("SELECT currency, rate FROM exchange_rates ORDER BY currency")
+            .fetch_all(pool)
+            .await?;
+
+        let mut rates: Vec<(Currency, f64)> = vec![(store, 1.0)];
+        for row in rows {
+            let code: String = row.try_get("currency")?;
+            let rate: f64 = row.try_get("rate")?;
+            if let Ok(currency) = Currency::from_str(&code.to_lowercase()) {
+                if currency != store && rate > 0.0 {
+                    rates.push((currency, rate));
+                }
+            }
+        }
+
+        let rates = Arc::new(rates);
+        *self.loaded.lock().expect("exchange rates lock poisoned") =
+            Some((Instant::now(), rates.clone()));
+        Ok(rates)
+    }
+}
+
+#[derive(Serialize)]
+struct DisplayOption {
+    code: String,
+    is_selected: bool,
+}
+
+/// Store currency plus the currency the visitor chose to see prices in.
+///
+/// `rate` converts store-currency amounts into the display currency and comes
+/// from the `exchange_rates` table; charges always use the store currency.
+#[derive(Serialize)]
+pub struct CurrencyContext {
+    pub display: Currency,
+    options: Vec<DisplayOption>,
+    rate: f64,
+    pub store: Currency,
+}
+
+impl CurrencyContext {
+    fn new(rates: &[(Currency, f64)], store: Currency, requested: Option<String>) -> Self {
+        let (display, rate) = requested
+            .and_then(|code| Currency::from_str(&code.to_lowercase()).ok())
+            .and_then(|requested| rates.iter().find(|(currency, _)| *currency == requested))
+            .copied()
+            .unwrap_or((store, 1.0));
+
+        let options = rates
+            .iter()
+            .map(|(currency, _)| DisplayOption {
+                code: currency.to_string().to_uppercase(),
+                is_selected: *currency == display,
+            })
+            .collect();
+
+        CurrencyContext {
+            display,
+            options,
+            rate,
+            store,
+        }
+    }
+}
+
+impl FromRequest for CurrencyContext {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = web::Data::<Pool<Postgres>>::extract(req);
+        let rates = web::Data::<ExchangeRates>::extract(req);
+        let store = web::Data::<StoreCurrency>::extract(req);
+        let requested = req
+            .cookie(DISPLAY_CURRENCY_COOKIE)
+            .map(|cookie| cookie.value().to_string());
+
+        Box::pin(async move {
+            let pool = pool.await?;
+            let rates = rates.await?;
+            let store = store.await?;
+            let rates = rates.get(&pool, store.0).await.map_err(AppError::from)?;
+            Ok(Self::new(&rates, store.0, requested))
+        })
+    }
+}
+
+/// Tera filter formatting a serialized `Money` value, optionally converted with
+/// `display=currency` (a serialized `CurrencyContext`), e.g.
+/// `{{ product.price | money(display=currency) }}`.
+pub fn money_filter(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let minor_units = value
+        .get("minor_units")
+        .and_then(Value::as_i64)
+        .ok_or_else(|| tera::Error::msg("`money` filter expects a Money value"))?;
+    let currency: Currency = value
+        .get("currency")
+        .cloned()
+        .and_then(|currency| tera::from_value(currency).ok())
+        .ok_or_else(|| tera::Error::msg("`money` filter expects a Money value"))?;
+
+    let display = args.get("display");
+    let target: Option<Currency> = display
+        .and_then(|display| display.get("display"))
+        .cloned()
+        .and_then(|display| tera::from_value(display).ok());
+    let rate = display
+        .and_then(|display| display.get("rate"))
+        .and_then(Value::as_f64)
+        .unwrap_or(1.0);
+
+    let formatted = match target {
+        Some(target) if target != currency => {
+            let scale = 10_f64
+                .powi(minor_unit_exponent(target) as i32 - minor_unit_exponent(currency) as i32);
+            let converted = (minor_units as f64 * rate * scale).round() as i64;
+            format!("≈{}", format_amount(converted, target))
+        }
+        _ => format_amount(minor_units, currency),
+    };
+
+    Ok(Value::String(formatted))
+}
@@ -1,90 +1,67 @@
-use actix_web::{web, HttpResponse};
-use csv::ReaderBuilder;
+pub mod currency;
+pub mod money;
+pub mod pagination;
+pub mod public_url;
+
+use crate::config::DatabaseConfig;
+use crate::errors::AppError;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::migrate::{Migration, Migrator};
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
-use std::env;
-use std::error::Error;
-use std::fs::File;
 use tera::{Context, Tera};
 
 pub fn render_template(
     tmpl: &web::Data<Tera>,
     template_name: &str,
     context: &Context,
-) -> HttpResponse {
-    match tmpl.render(template_name, context) {
-        Ok(rendered) => HttpResponse::Ok().body(rendered),
-        Err(err) => {
-            eprintln!("Error rendering _{}_ template: {:#?}", template_name, err);
-            HttpResponse::InternalServerError().body("Internal Server Error")
-        }
-    }
+) -> Result<HttpResponse, AppError> {
+    render_template_with_status(tmpl, template_name, context, StatusCode::OK)
+}
+
+pub fn render_template_with_status(
+    tmpl: &web::Data<Tera>,
+    template_name: &str,
+    context: &Context,
+    status: StatusCode,
+) -> Result<HttpResponse, AppError> {
+    let rendered = tmpl.render(template_name, context)?;
+    Ok(HttpResponse::build(status).body(rendered))
 }
 
-pub fn round_price(price: f64) -> f64 {
-    (price * 100.0).round() / 100.0
+pub fn is_htmx_request(req: &HttpRequest) -> bool {
+    req.headers().contains_key("HX-Request")
 }
 
-pub async fn create_database_pool() -> Result<Pool<Postgres>, sqlx::Error> {
-    let database_url = env::var("DATABASE_URL").expect("Missing `DATABASE_URL` env variable");
+pub async fn create_database_pool(config: &DatabaseConfig) -> Result<Pool<Postgres>, sqlx::Error> {
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(config.max_connections)
+        .connect(&config.url)
         .await?;
     Ok(pool)
 }
 
-pub async fn setup_database(pool: Pool<Postgres>) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS products (
-        category VARCHAR(50),
-        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-        description VARCHAR(255),
-        id SERIAL PRIMARY KEY,
-        image_url VARCHAR(255),
-        is_active BOOLEAN DEFAULT TRUE,
-        name VARCHAR(50) NOT NULL,
-        price DOUBLE PRECISION NOT NULL,
-        stock_quantity INT NOT NULL,
-        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP);",
-    )
-    .execute(&pool)
-    .await?;
-    Ok(())
-}
-
-pub async fn populate_database_with_mock_products(
-    pool: Pool<Postgres>,
-) -> Result<(), Box<dyn Error>> {
-    let file = File::open("src/mock_data/products.csv")?;
-    let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(file);
+/// Schema migrations from `migrations/`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
 
-    for result in rdr.records() {
-        let record = result?;
-
-        let id: i32 = record[0].parse()?;
-        let name = &record[1];
-        let description = &record[2];
-        let price: f64 = record[3].trim_start_matches('$').parse()?;
-        let stock_quantity: i32 = record[4].parse()?;
-        let category = &record[5];
-        let image_url = &record[6];
-        println!("Inserting product: id={}, name={}, description={}, price={}, stock_quantity={}, category={}, image_url={}", 
-            id, name, description, price, stock_quantity, category, image_url);
-
-        sqlx::query(
-            "INSERT INTO products (id, name, description, price, stock_quantity, category, image_url) 
-             VALUES ($1, $2, $3, $4, $5, $6, $7)
-             ON CONFLICT (id) DO NOTHING"
-        )
-        .bind(id)
-        .bind(name)
-        .bind(description)
-        .bind(price)
-        .bind(stock_quantity)
-        .bind(category)
-        .bind(image_url)
-        .execute(&pool)
+/// Migrations this binary ships that the database has not applied yet.
+pub async fn pending_migrations(
+    pool: &Pool<Postgres>,
+) -> Result<Vec<&'static Migration>, sqlx::Error> {
+    let is_tracked: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
         .await?;
-    }
-    Ok(())
+    let applied: Vec<i64> = match is_tracked {
+        true => {
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+                .fetch_all(pool)
+                .await?
+        }
+        false => Vec::new(),
+    };
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect())
 }
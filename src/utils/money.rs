@@ -0,0 +1,243 @@
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::ops::{Add, Mul};
+use stripe::Currency;
+
+/// An amount in integer minor units (e.g. cents) of a currency.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Money {
+    currency: Currency,
+    minor_units: i64,
+}
+
+/// Largest amount `parse` accepts, in minor units: the most Stripe charges at
+/// once. Keeping prices below it also keeps cart totals far from overflowing.
+pub const MAX_MINOR_UNITS: i64 = 99_999_999;
+
+/// Number of decimal places Stripe uses for the currency's minor unit.
+pub fn minor_unit_exponent(currency: Currency) -> u32 {
+    match currency {
+        Currency::BIF
+        | Currency::CLP
+        | Currency::DJF
+        | Currency::GNF
+        | Currency::JPY
+        | Currency::KMF
+        | Currency::KRW
+        | Currency::MGA
+        | Currency::PYG
+        | Currency::RWF
+        | Currency::UGX
+        | Currency::VND
+        | Currency::VUV
+        | Currency::XAF
+        | Currency::XOF
+        | Currency::XPF => 0,
+        _ => 2,
+    }
+}
+
+fn symbol(currency: Currency) -> Option<&'static str> {
+    match currency {
+        Currency::EUR => Some("€"),
+        Currency::GBP => Some("£"),
+        Currency::JPY => Some("¥"),
+        Currency::USD => Some("$"),
+        _ => None,
+    }
+}
+
+/// Formats an amount for display, e.g. `€2.80` or `2.80 CHF`.
+pub fn format_amount(minor_units: i64, currency: Currency) -> String {
+    let amount = Money::new(minor_units, currency).to_string();
+    match symbol(currency) {
+        Some(symbol) => format!("{}{}", symbol, amount),
+        None => format!("{} {}", amount, currency.to_string().to_uppercase()),
+    }
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        Money {
+            currency,
+            minor_units,
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(0, currency)
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    /// Parses a non-negative decimal amount such as `$2.79898989` or `7.5`,
+    /// rounding half up to the currency's minor unit without going through `f64`.
+    /// Amounts above `MAX_MINOR_UNITS` are rejected.
+    pub fn parse(value: &str, currency: Currency) -> Result<Self, String> {
+        let digits = value.trim().trim_start_matches('$').trim();
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let is_numeric = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !is_numeric(whole) || !is_numeric(fraction) {
+            return Err(format!("Invalid amount `{}`", value));
+        }
+
+        let exponent = minor_unit_exponent(currency) as usize;
+        let scale = 10_i64.pow(exponent as u32);
+        let kept: String = fraction
+            .chars()
+            .chain(std::iter::repeat('0'))
+            .take(exponent)
+            .collect();
+        let round_up = fraction
+            .chars()
+            .nth(exponent)
+            .is_some_and(|digit| digit >= '5');
+
+        let minor_units = whole
+            .parse::<i64>()
+            .ok()
+            .and_then(|whole| whole.checked_mul(scale))
+            .and_then(|units| units.checked_add(kept.parse::<i64>().unwrap_or(0)))
+            .and_then(|units| units.checked_add(round_up as i64))
+            .filter(|units| *units <= MAX_MINOR_UNITS)
+            .ok_or_else(|| format!("Amount `{}` is too large", value))?;
+
+        Ok(Money::new(minor_units, currency))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = minor_unit_exponent(self.currency);
+        if exponent == 0 {
+            return write!(f, "{}", self.minor_units);
+        }
+        let scale = 10_i64.pow(exponent);
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            units / scale as u64,
+            units % scale as u64,
+            width = exponent as usize
+        )
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Money", 3)?;
+        state.serialize_field("amount", &self.to_string())?;
+        state.serialize_field("currency", &self.currency)?;
+        state.serialize_field("minor_units", &self.minor_units)?;
+        state.end()
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        debug_assert_eq!(self.currency, other.currency);
+        Money::new(self.minor_units + other.minor_units, self.currency)
+    }
+}
+
+impl Mul<i32> for Money {
+    type Output = Money;
+
+    fn mul(self, quantity: i32) -> Money {
+        Money::new(self.minor_units * quantity as i64, self.currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cents(value: &str) -> Result<i64, String> {
+        Money::parse(value, Currency::EUR).map(|money| money.minor_units())
+    }
+
+    #[test]
+    fn parse_rounds_half_up_to_the_minor_unit() {
+        assert_eq!(cents("$2.79898989"), Ok(280));
+        assert_eq!(cents("2.795"), Ok(280));
+        assert_eq!(cents("2.7949999"), Ok(279));
+        assert_eq!(cents("0.005"), Ok(1));
+        assert_eq!(cents("0.004"), Ok(0));
+        assert_eq!(cents("9.999"), Ok(1000));
+    }
+
+    #[test]
+    fn parse_pads_short_fractions() {
+        assert_eq!(cents("7."), Ok(700));
+        assert_eq!(cents("7"), Ok(700));
+        assert_eq!(cents("7.5"), Ok(750));
+        assert_eq!(cents(" $ 12.05 "), Ok(1205));
+    }
+
+    #[test]
+    fn parse_uses_the_currency_minor_unit() {
+        let yen = |value| Money::parse(value, Currency::JPY).map(|money| money.minor_units());
+        assert_eq!(yen("1234"), Ok(1234));
+        assert_eq!(yen("1234.4"), Ok(1234));
+        assert_eq!(yen("1234.5"), Ok(1235));
+        assert_eq!(
+            Money::parse("1234.5", Currency::JPY).unwrap().to_string(),
+            "1235"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_amounts() {
+        for value in [
+            "", "$", "abc", "-1.00", "+1", "1.2.3", "1,50", ".5", "1.5e2", "１.00",
+        ] {
+            assert_eq!(
+                cents(value),
+                Err(format!("Invalid amount `{}`", value)),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_amounts_above_the_maximum() {
+        assert_eq!(cents("999999.99"), Ok(MAX_MINOR_UNITS));
+        assert_eq!(cents("999999.994"), Ok(MAX_MINOR_UNITS));
+        assert_eq!(
+            Money::parse("99999999", Currency::JPY).map(|money| money.minor_units()),
+            Ok(MAX_MINOR_UNITS)
+        );
+        for value in [
+            "1000000",
+            "999999.995",
+            "92233720368547758.07",
+            "99999999999999999999999",
+        ] {
+            assert_eq!(
+                cents(value),
+                Err(format!("Amount `{}` is too large", value)),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn the_largest_cart_line_does_not_overflow() {
+        let line = Money::new(MAX_MINOR_UNITS, Currency::EUR) * i32::MAX;
+        assert_eq!(line.minor_units(), MAX_MINOR_UNITS * i32::MAX as i64);
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PER_PAGE: i64 = 12;
+const MAX_PER_PAGE: i64 = 60;
+/// Far beyond any real listing; keeps the offset and page links from overflowing.
+const MAX_PAGE: i64 = 100_000;
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+impl PageQuery {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).clamp(1, MAX_PAGE)
+    }
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page() - 1) * self.per_page()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Pagination {
+    /// The page's own listing URL without `per_page`, for its canonical link.
+    pub canonical_url: String,
+    pub has_next: bool,
+    pub has_previous: bool,
+    pub next_url: String,
+    pub page: i64,
+    pub per_page: i64,
+    pub previous_url: String,
+    pub total: i64,
+    pub total_pages: i64,
+}
+
+impl Pagination {
+    /// `base_url` is the listing URL without paging parameters, e.g. `/` or `/search?q=tile`.
+    pub fn new(query: &PageQuery, total: i64, base_url: &str) -> Self {
+        let page = query.page();
+        let per_page = query.per_page();
+        let total_pages = ((total + per_page - 1) / per_page).max(1);
+        let separator = if base_url.contains('?') { '&' } else { '?' };
+        let page_url = |page: i64| {
+            format!(
+                "{}{}page={}&per_page={}",
+                base_url, separator, page, per_page
+            )
+        };
+
+        Pagination {
+            canonical_url: match page {
+                1 => base_url.to_string(),
+                page => format!("{}{}page={}", base_url, separator, page),
+            },
+            has_next: page < total_pages,
+            has_previous: page > 1,
+            next_url: page_url(page + 1),
+            page,
+            per_page,
+            previous_url: page_url((page - 1).max(1)),
+            total,
+            total_pages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_pages_are_clamped() {
+        let query = PageQuery {
+            page: Some(i64::MAX),
+            per_page: Some(i64::MAX),
+        };
+        assert_eq!(query.page(), MAX_PAGE);
+        assert_eq!(query.offset(), (MAX_PAGE - 1) * MAX_PER_PAGE);
+
+        let pagination = Pagination::new(&query, 3, "/");
+        assert!(!pagination.has_next);
+        assert_eq!(pagination.canonical_url, format!("/?page={}", MAX_PAGE));
+        assert_eq!(
+            pagination.next_url,
+            format!("/?page={}&per_page={}", MAX_PAGE + 1, MAX_PER_PAGE)
+        );
+
+        let query = PageQuery {
+            page: Some(i64::MIN),
+            per_page: None,
+        };
+        assert_eq!(query.page(), 1);
+        assert_eq!(query.offset(), 0);
+    }
+
+    #[test]
+    fn canonical_url_leaves_out_the_first_page_and_per_page() {
+        let query = |page| PageQuery {
+            page: Some(page),
+            per_page: Some(24),
+        };
+        assert_eq!(Pagination::new(&query(1), 100, "/").canonical_url, "/");
+        assert_eq!(
+            Pagination::new(&query(2), 100, "/search?q=tile").canonical_url,
+            "/search?q=tile&page=2"
+        );
+    }
+}
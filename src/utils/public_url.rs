@@ -0,0 +1,48 @@
+use actix_web::http::Uri;
+use std::collections::HashMap;
+use tera::Value;
+
+/// Address the store is reached at, from `PUBLIC_BASE_URL`, e.g.
+/// `https://shop.example.com`. Every absolute link the server hands out is
+/// built from it.
+#[derive(Clone)]
+pub struct PublicUrl(String);
+
+impl PublicUrl {
+    pub fn parse(base_url: &str) -> Result<Self, String> {
+        let base_url = base_url.trim().trim_end_matches('/');
+        let uri: Uri = base_url
+            .parse()
+            .map_err(|_| format!("{} is not a URL", base_url))?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => return Err(format!("{} must start with http:// or https://", base_url)),
+        }
+        if uri.host().is_none_or(str::is_empty) {
+            return Err(format!("{} has no host", base_url));
+        }
+        // Routes are served from the root, so a path would produce broken links.
+        if !matches!(uri.path(), "" | "/") || uri.query().is_some() || base_url.contains('#') {
+            return Err(format!(
+                "{} must not have a path, query or fragment",
+                base_url
+            ));
+        }
+        Ok(PublicUrl(base_url.to_string()))
+    }
+
+    /// Absolute URL of a root-relative `path`.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.0, path.trim_start_matches('/'))
+    }
+}
+
+/// Tera function `url(path="/cart")`, which turns a path into an absolute URL.
+pub fn url_function(
+    public_url: PublicUrl,
+) -> impl Fn(&HashMap<String, Value>) -> tera::Result<Value> + Send + Sync {
+    move |args| match args.get("path").and_then(Value::as_str) {
+        Some(path) => Ok(Value::String(public_url.url(path))),
+        None => Err(tera::Error::msg("`url` expects a `path` string argument")),
+    }
+}